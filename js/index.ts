@@ -1,6 +1,100 @@
 export interface EditOptions {
     unlock_pov: boolean,
     cut?: TickRange,
+    tick_numbering?: TickNumbering,
+    cut_event?: EventRange,
+    /**
+     * Cut by server ticks instead, so cuts from different demos of the same match line up
+     *
+     * Can't be combined with `cut_event`, if the demo only covers part of the range that part is cut
+     */
+    cut_server_ticks?: TickRange,
+    auto_director?: AutoDirectorOptions,
+    anonymize?: AnonymizeOptions,
+    filter_chat?: ChatFilterOptions,
+    remove_voice?: VoiceOptions,
+    remove_sounds?: SoundOptions,
+    keep_packet_types?: PacketKind[],
+    drop_packet_types?: PacketKind[],
+    metadata?: MetadataOptions,
+    embed_provenance?: boolean,
+    /** Removed in addition to `CloseCaption`, and `VGuiMenu` when unlocking the pov */
+    remove_user_messages?: UserMessageType[],
+    /** Messages that set up the entities, string tables or server info can't be removed */
+    remove_messages?: number[],
+    remove_idle?: IdleOptions,
+    /** Tick ranges to cut out of the demo, the parts around them are joined, the edit fails if the ranges overlap */
+    remove_ranges?: TickRange[],
+    /** Skip packets that can't be decoded instead of failing the edit */
+    lenient?: boolean,
+}
+
+export interface IdleOptions {
+    pauses?: boolean,
+    warmup?: boolean,
+    humiliation?: boolean,
+    /** Idle periods shorter than this many seconds are kept */
+    min_length?: number,
+}
+
+export type UserMessageType = 'Geiger' | 'Train' | 'HudText' | 'SayText' | 'SayText2' | 'TextMsg' | 'ResetHUD'
+    | 'GameTitle' | 'ItemPickup' | 'ShowMenu' | 'Shake' | 'Fade' | 'VGuiMenu' | 'Rumble' | 'CloseCaption'
+    | 'SendAudio' | 'VoiceMask' | 'RequestState' | 'Damage' | 'HintText' | 'KeyHintText' | 'HudMsg'
+    | 'AmmoDenied' | 'AchievementEvent' | 'UpdateRadar' | 'VoiceSubtitle' | 'HudNotify' | 'HudNotifyCustom'
+    | 'PlayerStatsUpdate' | 'PlayerIgnited' | 'PlayerIgnitedInv' | 'HudArenaNotify' | 'UpdateAchievement'
+    | 'TrainingMsg' | 'TrainingObjective' | 'DamageDodged' | 'PlayerJarated' | 'PlayerExtinguished'
+    | 'PlayerJaratedFade' | 'PlayerShieldBlocked' | 'BreakModel' | 'CheapBreakModel' | 'BreakModelPumpkin'
+    | 'BreakModelRocketDud' | 'CallVoteFailed' | 'VoteStart' | 'VotePass' | 'VoteFailed' | 'VoteSetup'
+    | 'PlayerBonusPoints' | 'SpawnFlyingBird' | 'PlayerGodRayEffect' | 'SPHapWeapEvent' | 'HapDmg'
+    | 'HapPunch' | 'HapSetDrag' | 'HapSet' | 'HapMeleeContact' | 'Unknown';
+
+export interface MetadataOptions {
+    server?: string,
+    nick?: string,
+    server_name?: string,
+}
+
+export type PacketKind = 'signon' | 'message' | 'sync_tick' | 'console_cmd' | 'user_cmd' | 'data_tables' | 'string_tables';
+
+export interface VoiceOptions {
+    players?: number[],
+}
+
+export interface SoundOptions {
+    patterns: string[],
+}
+
+export type ChatChannel = 'all' | 'team' | 'dead' | 'spectator' | 'server';
+
+/**
+ * Select chat messages to remove, empty `channels` or `players` match any channel or player
+ *
+ * When `words` is set, those words are masked in the selected messages instead of removing them
+ */
+export interface ChatFilterOptions {
+    channels?: ChatChannel[],
+    /** Steam ids of the players whose messages are selected, e.g. `[U:1:1234]` */
+    players?: string[],
+    words?: string[],
+}
+
+export interface AnonymizeOptions {
+    seed?: string,
+}
+
+export interface AnonymizeMapping {
+    names: { [name: string]: string },
+    steam_ids: { [steam_id: string]: string },
+}
+
+export interface AutoDirectorOptions {
+    min_dwell?: number,
+    priorities?: PlayerPriority[],
+}
+
+export interface PlayerPriority {
+    steam_id: string,
+    priority: number,
 }
 
 export interface TickRange {
@@ -8,11 +102,220 @@ export interface TickRange {
     to: number,
 }
 
+/**
+ * How the ticks in a cut are numbered, starting at 0, keeping the ticks from the source demo or starting at a given tick
+ */
+export type TickNumbering = 'zero' | 'original' | { base: number };
+
+export type Anchor = { type: 'round_start', round: number }
+    | { type: 'round_end', round: number }
+    | { type: 'kill', steam_id: string, kill: number }
+    | { type: 'capture', capture: number }
+    | { type: 'chat', text: string };
+
+export interface EventRange {
+    anchor: Anchor,
+    until?: Anchor,
+    pre_roll?: number,
+    post_roll?: number,
+}
+
+/**
+ * Edit the demo, rejects if the range to cut can't be found in the demo
+ */
 export async function edit(bytes: Uint8Array, options: EditOptions): Promise<Uint8Array> {
     let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
     return m.edit_js(bytes, options);
 }
 
+/**
+ * Get the pseudonyms that will be used when anonymizing the demo, rejects if the demo can't be parsed
+ */
+export async function anonymize_mapping(bytes: Uint8Array, options: AnonymizeOptions): Promise<AnonymizeMapping> {
+    let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
+    return m.anonymize_mapping_js(bytes, options);
+}
+
+export interface Provenance {
+    editor_version: string,
+    input_hash: string,
+    /** The header fields of the source demo, the server and nick are redacted when anonymizing or rewriting them */
+    server: string,
+    nick: string,
+    map: string,
+    ticks: number,
+    duration: number,
+    tick_offset: number,
+    /** The options that identify players, the anonymize seed and the metadata templates are redacted */
+    options: EditOptions,
+}
+
+export async function read_provenance(bytes: Uint8Array): Promise<Provenance | null> {
+    let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
+    return m.read_provenance_js(bytes);
+}
+
+/**
+ * A loaded demo that keeps the seek index between edits, making repeated cuts faster
+ *
+ * Call `free` once the session is no longer needed
+ */
+export interface EditSession {
+    /** Throws if the range to cut can't be found in the demo */
+    edit(options: EditOptions): Uint8Array,
+    /** The report for the last edit */
+    report(): EditReport,
+    free(): void,
+}
+
+export interface EditReport {
+    cut: TickRange | null,
+    removed: TickRange[],
+    /** Packets that couldn't be decoded in lenient mode */
+    skipped: SkippedPacket[],
+    adjustments: string[],
+    /** Problems in the output that can break playback, like message packets dropped in lenient mode */
+    integrity_errors: string[],
+    ticks: number,
+    frames: number,
+    duration: number,
+}
+
+export interface SkippedPacket {
+    tick: number,
+    /** Whether the packet was copied to the output undecoded or dropped */
+    copied: boolean,
+    error: string,
+}
+
+export async function edit_session(bytes: Uint8Array): Promise<EditSession> {
+    let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
+    return new m.EditSession(bytes);
+}
+
+export interface HighlightOptions {
+    steam_id: string,
+    /** Maximum number of seconds between kills in a streak */
+    streak_window?: number,
+    min_kills?: number,
+    /**
+     * Count hits on players in the air
+     *
+     * These aren't airshots, the game doesn't flag airshots so every hit on a player that isn't standing on the ground
+     * counts, including hitscan and melee hits and hits on players that just left the ground
+     */
+    airborne_hits?: boolean,
+    medic_drops?: boolean,
+    pre_roll?: number,
+    post_roll?: number,
+}
+
+export interface Highlight {
+    range: TickRange,
+    kills: number,
+    airborne_hits: number,
+    medic_drops: number,
+}
+
+/**
+ * Find the highlights of a player, ordered by tick, rejects if the demo can't be parsed
+ */
+export async function find_highlights(bytes: Uint8Array, options: HighlightOptions): Promise<Highlight[]> {
+    let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
+    return m.find_highlights_js(bytes, options);
+}
+
+/**
+ * Cut every highlight of a player into its own demo, applying the other edit options to each cut
+ */
+export async function cut_highlights(bytes: Uint8Array, options: HighlightOptions, edit_options: EditOptions): Promise<[Highlight, Uint8Array][]> {
+    const highlights = await find_highlights(bytes, options);
+    const session = await edit_session(bytes);
+    try {
+        return highlights.map(highlight => [highlight, session.edit({...edit_options, cut: highlight.range, cut_event: undefined, cut_server_ticks: undefined})]);
+    } finally {
+        session.free();
+    }
+}
+
+/**
+ * Cut all highlights of a player into a single demo with the time between the highlights removed,
+ * resolves to null if the player has no highlights
+ */
+export async function highlight_reel(bytes: Uint8Array, options: HighlightOptions, edit_options: EditOptions): Promise<[Highlight[], Uint8Array] | null> {
+    const highlights = await find_highlights(bytes, options);
+    if (highlights.length === 0) {
+        return null;
+    }
+    const gaps = highlights.slice(1)
+        .map((highlight, i) => ({from: highlights[i].range.to + 1, to: highlight.range.from - 1}))
+        .filter(gap => gap.from <= gap.to);
+    const cut = {from: highlights[0].range.from, to: highlights[highlights.length - 1].range.to};
+    const output = await edit(bytes, {
+        ...edit_options,
+        cut,
+        cut_event: undefined,
+        cut_server_ticks: undefined,
+        remove_ranges: [...(edit_options.remove_ranges || []), ...gaps],
+    });
+    return [highlights, output];
+}
+
+export interface BookmarkRanges {
+    ranges: TickRange[],
+    /** Lines of the bookmark file that couldn't be parsed */
+    unparsed: string[],
+}
+
+/**
+ * Get the tick ranges for the bookmarks made in this demo, padded by `pad` seconds on both sides,
+ * rejects if the demo header can't be read
+ *
+ * @param demo_path the file name of the demo, used to select its bookmarks
+ * @param bookmarks the contents of a `ds_mark` `_events.txt` or P-REC `KillStreaks.txt` file
+ */
+export async function bookmark_ranges(bytes: Uint8Array, demo_path: string, bookmarks: string, pad: number): Promise<BookmarkRanges> {
+    let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
+    return m.bookmark_ranges_js(bytes, demo_path, bookmarks, pad);
+}
+
+/**
+ * Cut the same server tick range out of multiple demos of the same match, applying the other edit options to each
+ *
+ * Every input gets its own result, a demo that doesn't cover the range doesn't fail the others,
+ * a demo that covers part of the range is cut to that part
+ */
+export async function cut_povs(inputs: Uint8Array[], range: TickRange, options: EditOptions): Promise<({ output: Uint8Array } | { error: string })[]> {
+    return Promise.all(inputs.map(input => edit(input, {...options, cut_server_ticks: range})
+        .then(output => ({output}), error => ({error: String(error)}))));
+}
+
+export interface RepairReport {
+    packets: number,
+    salvaged_bytes: number,
+    dropped_bytes: number,
+    added_stop: boolean,
+    /** The error that stopped reading the demo, if it ended in something other than a partial packet */
+    error: string | null,
+    ticks: number,
+    frames: number,
+    duration: number,
+}
+
+/**
+ * Repair a demo from a crashed game or killed server by dropping the unreadable tail,
+ * adding the missing stop packet and recomputing the header
+ */
+export async function repair(bytes: Uint8Array): Promise<[Uint8Array, RepairReport]> {
+    let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
+    const result = m.repair_js(bytes);
+    try {
+        return [result.output(), result.report()];
+    } finally {
+        result.free();
+    }
+}
+
 export async function count_ticks(bytes: Uint8Array): Promise<number> {
     let m = await import(/* webpackChunkName: "demos-tf-edit" */ "../pkg/index.js");
     return m.count_ticks(bytes);
@@ -0,0 +1,342 @@
+//! Generate the template demo used by the tests in `tests/`
+//!
+//! The template only has a server info message, the game event definitions the editor looks at,
+//! an empty `userinfo` string table and the data tables for a world and player class,
+//! enough for `DemoBuilder` to script the entities, players and events the tests need.
+//!
+//! ```text
+//! cargo run --example make_template
+//! ```
+
+use bitbuffer::{BitWrite, BitWriteStream, LittleEndian};
+use edit::DemoBuilder;
+use std::fs;
+use tf_demo_parser::demo::gameevent_gen::GameEventType;
+use tf_demo_parser::demo::gamevent::{GameEventDefinition, GameEventEntry, GameEventValueType};
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::gameevent::GameEventListMessage;
+use tf_demo_parser::demo::message::stringtable::CreateStringTableMessage;
+use tf_demo_parser::demo::message::{Message, ServerInfoMessage};
+use tf_demo_parser::demo::packet::datatable::{
+    DataTablePacket, ParseSendTable, SendTableName, ServerClass,
+};
+use tf_demo_parser::demo::packet::message::MessagePacket;
+use tf_demo_parser::demo::packet::stop::StopPacket;
+use tf_demo_parser::demo::packet::stringtable::StringTable;
+use tf_demo_parser::demo::packet::synctick::SyncTickPacket;
+use tf_demo_parser::demo::packet::{Packet, PacketType};
+use tf_demo_parser::demo::parser::{DemoHandler, Encode};
+use tf_demo_parser::demo::sendprop::{
+    RawSendPropDefinition, SendPropFlag, SendPropFlags, SendPropIdentifier, SendPropType,
+};
+
+const OUTPUT: &str = "tests/data/template.dem";
+
+/// The events with their entries, in the order the parser writes the fields of the event
+const EVENTS: &[(&str, &[(&str, GameEventValueType)])] = {
+    use GameEventValueType::{Boolean, Byte, Float, Long, Short, String};
+    &[
+        (
+            "player_connect_client",
+            &[
+                ("name", String),
+                ("index", Byte),
+                ("userid", Short),
+                ("networkid", String),
+                ("bot", Short),
+            ],
+        ),
+        (
+            "player_disconnect",
+            &[
+                ("userid", Short),
+                ("reason", String),
+                ("name", String),
+                ("networkid", String),
+                ("bot", Short),
+            ],
+        ),
+        (
+            "player_changename",
+            &[("userid", Short), ("oldname", String), ("newname", String)],
+        ),
+        ("player_say", &[("userid", Short), ("text", String)]),
+        (
+            "player_death",
+            &[
+                ("userid", Short),
+                ("victim_entindex", Long),
+                ("inflictor_entindex", Long),
+                ("attacker", Short),
+                ("weapon", String),
+                ("weaponid", Short),
+                ("damagebits", Long),
+                ("customkill", Short),
+                ("assister", Short),
+                ("weapon_logclassname", String),
+                ("stun_flags", Short),
+                ("death_flags", Short),
+                ("silent_kill", Boolean),
+                ("playerpenetratecount", Short),
+                ("assister_fallback", String),
+                ("kill_streak_total", Short),
+                ("kill_streak_wep", Short),
+                ("kill_streak_assist", Short),
+                ("kill_streak_victim", Short),
+                ("ducks_streaked", Short),
+                ("duck_streak_total", Short),
+                ("duck_streak_assist", Short),
+                ("duck_streak_victim", Short),
+                ("rocket_jump", Boolean),
+                ("weapon_def_index", Long),
+                ("crit_type", Short),
+            ],
+        ),
+        (
+            "player_hurt",
+            &[
+                ("userid", Short),
+                ("health", Short),
+                ("attacker", Short),
+                ("damageamount", Short),
+                ("custom", Short),
+                ("showdisguisedcrit", Boolean),
+                ("crit", Boolean),
+                ("minicrit", Boolean),
+                ("allseecrit", Boolean),
+                ("weaponid", Short),
+                ("bonuseffect", Byte),
+            ],
+        ),
+        (
+            "medic_death",
+            &[
+                ("userid", Short),
+                ("attacker", Short),
+                ("healing", Short),
+                ("charged", Boolean),
+            ],
+        ),
+        (
+            "player_chargedeployed",
+            &[("userid", Short), ("targetid", Short)],
+        ),
+        (
+            "teamplay_point_captured",
+            &[
+                ("cp", Byte),
+                ("cpname", String),
+                ("team", Byte),
+                ("cappers", String),
+            ],
+        ),
+        ("teamplay_round_start", &[("full_reset", Boolean)]),
+        (
+            "teamplay_round_win",
+            &[
+                ("team", Byte),
+                ("winreason", Byte),
+                ("flagcaplimit", Short),
+                ("full_round", Short),
+                ("round_time", Float),
+                ("losing_team_num_caps", Short),
+                ("was_sudden_death", Byte),
+            ],
+        ),
+        ("teamplay_round_stalemate", &[("reason", Byte)]),
+    ]
+};
+
+fn int_prop(table: &'static str, name: &'static str, bits: u32) -> RawSendPropDefinition {
+    RawSendPropDefinition {
+        prop_type: SendPropType::Int,
+        name: name.into(),
+        identifier: SendPropIdentifier::new(table, name),
+        flags: SendPropFlags::default() | SendPropFlag::Unsigned,
+        table_name: None,
+        low_value: Some(0.0),
+        high_value: Some(0.0),
+        bit_count: Some(bits),
+        element_count: None,
+        array_property: None,
+        original_bit_count: Some(bits),
+    }
+}
+
+fn table_prop(
+    table: &'static str,
+    name: &'static str,
+    target: &'static str,
+) -> RawSendPropDefinition {
+    RawSendPropDefinition {
+        prop_type: SendPropType::DataTable,
+        name: name.into(),
+        identifier: SendPropIdentifier::new(table, name),
+        flags: SendPropFlags::default(),
+        table_name: Some(target.into()),
+        low_value: None,
+        high_value: None,
+        bit_count: None,
+        element_count: None,
+        array_property: None,
+        original_bit_count: None,
+    }
+}
+
+fn send_table(name: &'static str, props: Vec<RawSendPropDefinition>) -> ParseSendTable {
+    ParseSendTable {
+        name: name.into(),
+        props,
+        needs_decoder: true,
+    }
+}
+
+fn server_class(id: u16, name: &str, data_table: &'static str) -> ServerClass {
+    ServerClass {
+        id: id.into(),
+        name: name.to_string().into(),
+        data_table: SendTableName::from(data_table),
+    }
+}
+
+fn event_definitions() -> Vec<GameEventDefinition> {
+    EVENTS
+        .iter()
+        .enumerate()
+        .map(|(id, (name, entries))| GameEventDefinition {
+            // the id is only constructable through deserializing, it has to match the index in the list
+            id: serde_json::from_value(id.into()).unwrap(),
+            event_type: GameEventType::from_type_name(name),
+            entries: entries
+                .iter()
+                .map(|(name, kind)| GameEventEntry {
+                    name: name.to_string(),
+                    kind: *kind,
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+fn main() {
+    let mut header = Header {
+        demo_type: "HL2DEMO".into(),
+        version: 3,
+        protocol: 24,
+        server: "template".into(),
+        nick: "SourceTV Demo".into(),
+        map: "template".into(),
+        game: "tf".into(),
+        duration: 0.0,
+        ticks: 0,
+        frames: 0,
+        signon: 0,
+    };
+
+    let server_classes = vec![
+        server_class(0, "CWorld", "DT_WorldTable"),
+        server_class(1, "CTFPlayer", "DT_TFPlayer"),
+    ];
+    let packets = vec![
+        Packet::Signon(MessagePacket {
+            tick: 0.into(),
+            messages: vec![
+                Message::ServerInfo(Box::new(ServerInfoMessage {
+                    version: 24,
+                    server_count: 1,
+                    stv: true,
+                    dedicated: true,
+                    max_crc: 0,
+                    max_classes: server_classes.len() as u16,
+                    map_hash: [0; 16],
+                    player_slot: 0,
+                    max_player_count: 24,
+                    interval_per_tick: 0.015,
+                    platform: "l".into(),
+                    game: "tf".into(),
+                    map: "template".into(),
+                    skybox: "sky_template".into(),
+                    server_name: "template".into(),
+                    replay: false,
+                })),
+                Message::GameEventList(GameEventListMessage {
+                    event_list: event_definitions(),
+                }),
+                Message::CreateStringTable(CreateStringTableMessage {
+                    table: StringTable {
+                        name: "userinfo".into(),
+                        entries: Vec::new(),
+                        max_entries: 256,
+                        fixed_user_data_size: None,
+                        client_entries: None,
+                        compressed: false,
+                    },
+                }),
+            ],
+            ..MessagePacket::default()
+        }),
+        Packet::DataTables(DataTablePacket {
+            tick: 0.into(),
+            tables: vec![
+                send_table(
+                    "DT_BaseEntity",
+                    vec![int_prop("DT_BaseEntity", "m_iTeamNum", 6)],
+                ),
+                send_table(
+                    "DT_WorldTable",
+                    vec![table_prop("DT_WorldTable", "baseclass", "DT_BaseEntity")],
+                ),
+                send_table(
+                    "DT_BasePlayer",
+                    vec![
+                        table_prop("DT_BasePlayer", "baseclass", "DT_BaseEntity"),
+                        int_prop("DT_BasePlayer", "m_fFlags", 11),
+                    ],
+                ),
+                send_table(
+                    "DT_TFPlayerShared",
+                    vec![int_prop("DT_TFPlayerShared", "m_nPlayerCond", 32)],
+                ),
+                send_table(
+                    "DT_TFPlayer",
+                    vec![
+                        table_prop("DT_TFPlayer", "baseclass", "DT_BasePlayer"),
+                        table_prop("DT_TFPlayer", "m_Shared", "DT_TFPlayerShared"),
+                    ],
+                ),
+            ],
+            server_classes,
+        }),
+        Packet::SyncTick(SyncTickPacket { tick: 0.into() }),
+    ];
+
+    let mut body = Vec::new();
+    {
+        let mut body_stream = BitWriteStream::new(&mut body, LittleEndian);
+        let mut handler = DemoHandler::default();
+        handler.handle_header(&header);
+        for packet in packets {
+            packet
+                .encode(&mut body_stream, &handler.state_handler)
+                .unwrap();
+            handler.handle_packet(packet).unwrap();
+        }
+        header.signon = (body_stream.bit_len() / 8) as u32;
+
+        PacketType::Stop.write(&mut body_stream).unwrap();
+        StopPacket { tick: 0.into() }
+            .encode(&mut body_stream, &handler.state_handler)
+            .unwrap();
+    }
+
+    let mut out_buffer = Vec::new();
+    {
+        let mut out_stream = BitWriteStream::new(&mut out_buffer, LittleEndian);
+        header.write(&mut out_stream).unwrap();
+    }
+    out_buffer.extend_from_slice(&body);
+
+    DemoBuilder::from_template(&out_buffer).expect("generated template can't be parsed");
+    fs::write(OUTPUT, &out_buffer).unwrap();
+    println!("written {} bytes to {}", out_buffer.len(), OUTPUT);
+}
@@ -0,0 +1,172 @@
+use crate::header::interval_per_tick;
+use crate::users::user_from_entry;
+use crate::{parse_failed, TickRange};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tf_demo_parser::demo::data::{DemoTick, UserInfo};
+use tf_demo_parser::demo::gamevent::GameEvent;
+use tf_demo_parser::demo::message::usermessage::UserMessage;
+use tf_demo_parser::demo::message::{Message, MessageType};
+use tf_demo_parser::demo::packet::stringtable::StringTableEntry;
+use tf_demo_parser::demo::parser::analyser::UserId;
+use tf_demo_parser::demo::parser::MessageHandler;
+use tf_demo_parser::{Demo, DemoParser, ParserState};
+
+/// A moment in the demo, found by looking for an event
+///
+/// Counts start at 1, so `{"type": "kill", "steam_id": "[U:1:1234]", "kill": 3}` is the third kill by that player.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Anchor {
+    RoundStart {
+        round: u32,
+    },
+    RoundEnd {
+        round: u32,
+    },
+    Kill {
+        steam_id: String,
+        kill: u32,
+    },
+    Capture {
+        capture: u32,
+    },
+    /// The first chat message containing the text, ignoring case
+    Chat {
+        text: String,
+    },
+}
+
+/// A cut around an event, from `pre_roll` seconds before the anchor until `post_roll` seconds after
+///
+/// When `until` is set, the cut ends `post_roll` seconds after that anchor instead.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EventRange {
+    pub anchor: Anchor,
+    #[serde(default)]
+    pub until: Option<Anchor>,
+    #[serde(default)]
+    pub pre_roll: f32,
+    #[serde(default)]
+    pub post_roll: f32,
+}
+
+#[derive(Default)]
+struct AnchorAnalyser {
+    users: BTreeMap<UserId, UserInfo>,
+    round_starts: Vec<DemoTick>,
+    round_ends: Vec<DemoTick>,
+    kills: Vec<(DemoTick, UserId)>,
+    captures: Vec<DemoTick>,
+    chat: Vec<(DemoTick, String)>,
+}
+
+impl AnchorAnalyser {
+    fn handle_event(&mut self, event: &GameEvent, tick: DemoTick) {
+        match event {
+            GameEvent::TeamPlayRoundStart(_) => self.round_starts.push(tick),
+            GameEvent::TeamPlayRoundWin(_) | GameEvent::TeamPlayRoundStalemate(_) => {
+                self.round_ends.push(tick)
+            }
+            GameEvent::PlayerDeath(event) if event.attacker != event.user_id => {
+                self.kills.push((tick, event.attacker.into()))
+            }
+            GameEvent::TeamPlayPointCaptured(_) => self.captures.push(tick),
+            _ => {}
+        }
+    }
+
+    fn find(&self, anchor: &Anchor) -> Option<DemoTick> {
+        let nth = |ticks: &[DemoTick], n: u32| ticks.get((n as usize).checked_sub(1)?).copied();
+        match anchor {
+            Anchor::RoundStart { round } => nth(&self.round_starts, *round),
+            Anchor::RoundEnd { round } => nth(&self.round_ends, *round),
+            Anchor::Kill { steam_id, kill } => {
+                let kills: Vec<DemoTick> = self
+                    .kills
+                    .iter()
+                    .filter(|(_, attacker)| {
+                        self.users
+                            .get(attacker)
+                            .map(|user| &user.player_info.steam_id == steam_id)
+                            .unwrap_or_default()
+                    })
+                    .map(|(tick, _)| *tick)
+                    .collect();
+                nth(&kills, *kill)
+            }
+            Anchor::Capture { capture } => nth(&self.captures, *capture),
+            Anchor::Chat { text } => {
+                let text = text.to_lowercase();
+                self.chat
+                    .iter()
+                    .find(|(_, message)| message.to_lowercase().contains(&text))
+                    .map(|(tick, _)| *tick)
+            }
+        }
+    }
+}
+
+impl MessageHandler for AnchorAnalyser {
+    type Output = Self;
+
+    fn does_handle(message_type: MessageType) -> bool {
+        matches!(
+            message_type,
+            MessageType::GameEvent | MessageType::UserMessage
+        )
+    }
+
+    fn handle_message(&mut self, message: &Message, tick: DemoTick, _parser_state: &ParserState) {
+        match message {
+            Message::GameEvent(message) => self.handle_event(&message.event, tick),
+            Message::UserMessage(UserMessage::SayText2(message)) => {
+                self.chat.push((tick, message.text.to_string()))
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_string_entry(
+        &mut self,
+        table: &str,
+        index: usize,
+        entry: &StringTableEntry,
+        _parser_state: &ParserState,
+    ) {
+        if let Some(user) = user_from_entry(table, index, entry) {
+            self.users.insert(user.player_info.user_id, user);
+        }
+    }
+
+    fn into_output(self, _state: &ParserState) -> Self::Output {
+        self
+    }
+}
+
+/// Find the tick range for the event range
+///
+/// Fails if the demo can't be parsed or one of the anchors can't be found in the demo.
+pub fn resolve_range(demo: &Demo, range: &EventRange) -> Result<TickRange, String> {
+    let parser = DemoParser::new_with_analyser(demo.get_stream(), AnchorAnalyser::default());
+    let (header, analyser) = parser.parse().map_err(parse_failed)?;
+    let interval = interval_per_tick(&header);
+
+    let find = |anchor: &Anchor| {
+        analyser
+            .find(anchor)
+            .ok_or_else(|| format!("Couldn't find {:?} in the demo", anchor))
+    };
+    let start = find(&range.anchor)?;
+    let end = match &range.until {
+        Some(until) => find(until)?,
+        None => start,
+    };
+    let pre_roll = (range.pre_roll.max(0.0) / interval) as u32;
+    let post_roll = (range.post_roll.max(0.0) / interval) as u32;
+
+    Ok(TickRange {
+        from: DemoTick::from(u32::from(start).saturating_sub(pre_roll)),
+        to: end + post_roll,
+    })
+}
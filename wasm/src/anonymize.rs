@@ -0,0 +1,291 @@
+use crate::mutate::{MessageMutator, MutatorList, PacketMutator};
+use crate::users::user_from_entry;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use tf_demo_parser::demo::data::{MaybeUtf8String, UserInfo};
+use tf_demo_parser::demo::gamevent::GameEvent;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::usermessage::{ChatMessageKind, UserMessage};
+use tf_demo_parser::demo::message::{Message, MessageType};
+use tf_demo_parser::demo::packet::stringtable::{StringTable, StringTableEntry};
+use tf_demo_parser::demo::packet::Packet;
+use tf_demo_parser::demo::parser::MessageHandler;
+use tf_demo_parser::{Demo, DemoParser, ParseError, ParserState};
+
+/// Names shorter than this aren't replaced when they're mentioned in chat or the server name
+const MIN_TEXT_NAME_LENGTH: usize = 3;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AnonymizeOptions {
+    /// Seed for the pseudonyms, use the same seed to get the same pseudonyms across demos
+    #[serde(default)]
+    pub seed: String,
+}
+
+/// The pseudonyms used for every player in the demo
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AnonymizeMapping {
+    pub names: BTreeMap<String, String>,
+    pub steam_ids: BTreeMap<String, String>,
+}
+
+impl AnonymizeMapping {
+    pub fn new(demo: &Demo, options: &AnonymizeOptions) -> Result<Self, ParseError> {
+        let parser = DemoParser::new_with_analyser(demo.get_stream(), UserCollector::default());
+        let (_, users) = parser.parse()?;
+
+        let mut mapping = AnonymizeMapping::default();
+        for (name, steam_id) in users.users {
+            if steam_id == "BOT" {
+                continue;
+            }
+            let hash = hash(&options.seed, &steam_id);
+            mapping
+                .names
+                .insert(name, format!("Player {:06x}", hash & 0xffffff));
+            // universe 0 and account type `I` (invalid) can't belong to a real account
+            mapping
+                .steam_ids
+                .insert(steam_id, format!("[I:0:{}]", hash % 1_000_000_000));
+        }
+        Ok(mapping)
+    }
+
+    fn name(&self, name: &str) -> Option<&str> {
+        self.names.get(name).map(String::as_str)
+    }
+
+    fn steam_id(&self, steam_id: &str) -> Option<&str> {
+        self.steam_ids.get(steam_id).map(String::as_str)
+    }
+
+    fn anonymize_name(&self, name: &mut MaybeUtf8String) {
+        if let Some(pseudonym) = self.name(name.as_ref()) {
+            *name = pseudonym.into();
+        }
+    }
+
+    fn anonymize_steam_id(&self, steam_id: &mut MaybeUtf8String) {
+        if let Some(pseudonym) = self.steam_id(steam_id.as_ref()) {
+            *steam_id = pseudonym.into();
+        }
+    }
+
+    /// Replace every name mentioned in free text
+    ///
+    /// Longer names are replaced first so a name that contains another name is replaced as a whole,
+    /// names shorter than `MIN_TEXT_NAME_LENGTH` are left alone since they match too many unrelated words.
+    fn anonymize_text(&self, text: &str) -> String {
+        let mut names: Vec<_> = self
+            .names
+            .iter()
+            .filter(|(name, _)| name.chars().count() >= MIN_TEXT_NAME_LENGTH)
+            .collect();
+        names.sort_by_key(|(name, _)| std::cmp::Reverse(name.len()));
+        names
+            .into_iter()
+            .fold(text.to_string(), |text, (name, pseudonym)| {
+                text.replace(name.as_str(), pseudonym)
+            })
+    }
+
+    fn anonymize_header(&self, header: &mut Header) {
+        if let Some(pseudonym) = self.name(&header.nick) {
+            header.nick = pseudonym.into();
+        }
+    }
+
+    fn anonymize_user_entry(&self, index: u16, entry: &mut StringTableEntry) {
+        let user = UserInfo::parse_from_string_table(
+            index,
+            entry.text.as_deref(),
+            entry.extra_data.as_ref().map(|data| data.data.clone()),
+        );
+        if let Ok(Some(mut user)) = user {
+            let info = &mut user.player_info;
+            if let Some(name) = self.name(&info.name) {
+                info.name = name.into();
+            }
+            if let Some(steam_id) = self.steam_id(&info.steam_id) {
+                info.steam_id = steam_id.into();
+                // the friends id is the account id and the custom files are the crc of the player's spray,
+                // both identify the player
+                info.friends_id = 0;
+                info.friends_name_bytes = [0; 32];
+                info.custom_file = [0; 4];
+                info.files_downloaded = 0;
+            }
+            // only take the extra data, the encoded text is off by one from the original
+            if let Ok(encoded) = user.encode_to_string_table() {
+                entry.extra_data = encoded.extra_data;
+            }
+        }
+    }
+
+    fn anonymize_event(&self, event: &mut GameEvent) {
+        match event {
+            GameEvent::PlayerConnect(event) => {
+                self.anonymize_name(&mut event.name);
+                self.anonymize_steam_id(&mut event.network_id);
+            }
+            GameEvent::PlayerConnectClient(event) => {
+                self.anonymize_name(&mut event.name);
+                self.anonymize_steam_id(&mut event.network_id);
+            }
+            GameEvent::PlayerInfo(event) => {
+                self.anonymize_name(&mut event.name);
+                self.anonymize_steam_id(&mut event.network_id);
+            }
+            GameEvent::PlayerDisconnect(event) => {
+                self.anonymize_name(&mut event.name);
+                self.anonymize_steam_id(&mut event.network_id);
+            }
+            GameEvent::PlayerChangeName(event) => {
+                self.anonymize_name(&mut event.old_name);
+                self.anonymize_name(&mut event.new_name);
+            }
+            GameEvent::PlayerDeath(event) => {
+                self.anonymize_name(&mut event.assister_fallback);
+            }
+            _ => {}
+        }
+    }
+
+    fn anonymize_user_message(&self, message: &mut UserMessage) {
+        match message {
+            UserMessage::SayText2(message) => {
+                if let Some(from) = message.from.as_mut() {
+                    self.anonymize_name(from);
+                }
+                if message.kind == ChatMessageKind::NameChange {
+                    self.anonymize_name(&mut message.text);
+                } else {
+                    // players mentioned in the chat itself
+                    message.text = self.anonymize_text(message.text.as_ref()).as_str().into();
+                }
+            }
+            UserMessage::Text(message) => {
+                for substitute in message.substitute.iter_mut() {
+                    self.anonymize_name(substitute);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn hash(seed: &str, steam_id: &str) -> u64 {
+    // fnv-1a, we need the hash to be stable between versions and platforms
+    seed.bytes()
+        .chain(Some(0))
+        .chain(steam_id.bytes())
+        .fold(0xcbf29ce484222325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+        })
+}
+
+#[derive(Default)]
+struct UserCollector {
+    users: BTreeMap<String, String>,
+}
+
+impl MessageHandler for UserCollector {
+    type Output = Self;
+
+    fn does_handle(_message_type: MessageType) -> bool {
+        false
+    }
+
+    fn handle_string_entry(
+        &mut self,
+        table: &str,
+        index: usize,
+        entry: &StringTableEntry,
+        _parser_state: &ParserState,
+    ) {
+        if let Some(user) = user_from_entry(table, index, entry) {
+            self.users
+                .insert(user.player_info.name, user.player_info.steam_id);
+        }
+    }
+
+    fn into_output(self, _state: &ParserState) -> Self::Output {
+        self
+    }
+}
+
+struct Anonymizer {
+    mapping: AnonymizeMapping,
+    table_names: RefCell<Vec<String>>,
+}
+
+impl Anonymizer {
+    fn handle_table(&self, table: &mut StringTable) {
+        self.table_names.borrow_mut().push(table.name.to_string());
+        if table.name == "userinfo" {
+            for (index, entry) in table.entries.iter_mut() {
+                self.mapping.anonymize_user_entry(*index, entry);
+            }
+        }
+    }
+}
+
+impl PacketMutator for Anonymizer {
+    fn mutate_packet(&self, packet: &mut Packet, state: &ParserState) {
+        match packet {
+            Packet::StringTables(packet) => {
+                for table in packet.tables.iter_mut() {
+                    self.handle_table(table);
+                }
+            }
+            Packet::Message(packet) | Packet::Signon(packet) => {
+                for message in packet.messages.iter_mut() {
+                    self.mutate_message(message, state);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MessageMutator for Anonymizer {
+    fn mutate_message(&self, message: &mut Message, _state: &ParserState) {
+        match message {
+            Message::CreateStringTable(message) => self.handle_table(&mut message.table),
+            Message::UpdateStringTable(message) => {
+                let is_user_info = self
+                    .table_names
+                    .borrow()
+                    .get(message.table_id as usize)
+                    .map(|name| name == "userinfo")
+                    .unwrap_or_default();
+                if is_user_info {
+                    for (index, entry) in message.entries.iter_mut() {
+                        self.mapping.anonymize_user_entry(*index, entry);
+                    }
+                }
+            }
+            Message::UserMessage(message) => self.mapping.anonymize_user_message(message),
+            Message::GameEvent(message) => self.mapping.anonymize_event(&mut message.event),
+            Message::ServerInfo(info) => {
+                info.server_name = self.mapping.anonymize_text(&info.server_name);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Replace the names and steam ids of all players with pseudonyms
+///
+/// Names are also replaced where they're mentioned in chat messages and the server name,
+/// but misspelled or shortened names in chat can't be recognized and are kept as is.
+pub fn anonymize(mutators: &mut MutatorList, mapping: AnonymizeMapping) {
+    let header_mapping = mapping.clone();
+    mutators
+        .push_header_mutator(move |header: &mut Header| header_mapping.anonymize_header(header));
+    mutators.push_packet_mutator(Anonymizer {
+        mapping,
+        table_names: RefCell::default(),
+    });
+}
@@ -0,0 +1,175 @@
+use crate::header::interval_per_tick;
+use crate::TickRange;
+use bitbuffer::BitRead;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::{Demo, ParseError};
+
+/// A bookmark or killstreak recorded by `ds_mark` or P-REC
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Bookmark {
+    /// Name of the demo without the `.dem` extension
+    pub demo: String,
+    pub tick: DemoTick,
+    /// The kind of bookmark as written in the file, e.g. "Bookmark" or "Killstreak 3"
+    pub kind: String,
+}
+
+/// The bookmarks read from a bookmark file
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct BookmarkFile {
+    pub bookmarks: Vec<Bookmark>,
+    /// Lines that aren't bookmarks or separators, these are most likely bookmarks in a format we don't know
+    pub unparsed: Vec<String>,
+}
+
+/// Parse the bookmarks from a `_events.txt` or `KillStreaks.txt` file
+///
+/// Every bookmark is on its own line, formatted as `[2023/01/15 18:30] Killstreak 3 ("demo name" at 5234)`.
+/// The timestamp is optional and the tick can be followed by "tick", empty lines and the `>` lines
+/// that separate recording sessions are skipped.
+pub fn parse_bookmarks(text: &str) -> BookmarkFile {
+    let mut file = BookmarkFile::default();
+    for line in text.lines().map(str::trim) {
+        if line.chars().all(|c| c == '>') {
+            continue;
+        }
+        match parse_line(line) {
+            Some(bookmark) => file.bookmarks.push(bookmark),
+            None => file.unparsed.push(line.to_string()),
+        }
+    }
+    file
+}
+
+fn parse_line(line: &str) -> Option<Bookmark> {
+    let rest = match line.strip_prefix('[') {
+        Some(timestamped) => timestamped.split_once(']')?.1.trim_start(),
+        None => line,
+    };
+    let (kind, rest) = rest.split_once(" (\"")?;
+    let (demo, tick) = rest.strip_suffix(')')?.rsplit_once("\" at ")?;
+    let tick = tick.trim();
+    let tick = tick
+        .strip_suffix("ticks")
+        .or_else(|| tick.strip_suffix("tick"))
+        .unwrap_or(tick);
+    let tick: u32 = tick.trim().parse().ok()?;
+    Some(Bookmark {
+        demo: demo_name(demo).to_string(),
+        tick: tick.into(),
+        kind: kind.trim().to_string(),
+    })
+}
+
+/// Strip the directory and extension from a demo path
+fn demo_name(path: &str) -> &str {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    name.strip_suffix(".dem").unwrap_or(name)
+}
+
+/// The tick ranges for the bookmarks in the demo, padded by `pad` seconds on both sides
+///
+/// `demo_path` is the file name or path of the demo, used to select the bookmarks made in that demo.
+pub fn bookmark_ranges(
+    input: &[u8],
+    demo_path: &str,
+    bookmarks: &[Bookmark],
+    pad: f32,
+) -> Result<Vec<TickRange>, ParseError> {
+    let demo = Demo::new(input);
+    let header = Header::read(&mut demo.get_stream())?;
+    let pad = (pad.max(0.0) / interval_per_tick(&header)) as u32;
+    let name = demo_name(demo_path);
+
+    Ok(bookmarks
+        .iter()
+        .filter(|bookmark| bookmark.demo.eq_ignore_ascii_case(name))
+        .map(|bookmark| TickRange {
+            from: DemoTick::from(u32::from(bookmark.tick).saturating_sub(pad)),
+            to: bookmark.tick + pad,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bookmark(demo: &str, tick: u32, kind: &str) -> Bookmark {
+        Bookmark {
+            demo: demo.to_string(),
+            tick: tick.into(),
+            kind: kind.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_ds_mark() {
+        let file = parse_bookmarks(
+            ">\n\
+             [2023/01/15 18:30] Bookmark (\"2023-01-15_18-21-07\" at 5234)\n\
+             [2023/01/15 18:32] Killstreak 3 (\"2023-01-15_18-21-07\" at 13012)\n\
+             >\n",
+        );
+        assert_eq!(
+            file.bookmarks,
+            vec![
+                bookmark("2023-01-15_18-21-07", 5234, "Bookmark"),
+                bookmark("2023-01-15_18-21-07", 13012, "Killstreak 3"),
+            ]
+        );
+        assert!(file.unparsed.is_empty());
+    }
+
+    #[test]
+    fn parse_prec() {
+        let file = parse_bookmarks(
+            "[2014/03/22 22:42] Kill Streak:3 (\"demos/2014-03-22_22-37-02.dem\" at 9473)\r\n\
+             [2014/03/22 22:45] Bookmark (\"demos\\pov_cp_process.dem\" at 12001)\r\n",
+        );
+        assert_eq!(
+            file.bookmarks,
+            vec![
+                bookmark("2014-03-22_22-37-02", 9473, "Kill Streak:3"),
+                bookmark("pov_cp_process", 12001, "Bookmark"),
+            ]
+        );
+        assert!(file.unparsed.is_empty());
+    }
+
+    #[test]
+    fn parse_variants() {
+        let file = parse_bookmarks(
+            "[2023/01/15 18:30] Bookmark (\"match\" at 5234 tick)\n\
+             Bookmark (\"match\" at 6000)\n\
+             [2023/01/15 18:31]Killstreak 4 (\"match \"final\"\" at 7000 ticks)\n",
+        );
+        assert_eq!(
+            file.bookmarks,
+            vec![
+                bookmark("match", 5234, "Bookmark"),
+                bookmark("match", 6000, "Bookmark"),
+                bookmark("match \"final\"", 7000, "Killstreak 4"),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_unparsed() {
+        let file = parse_bookmarks(
+            "[2023/01/15 18:30] Bookmark (\"match\" at soon)\n\
+             \n\
+             Recording started\n",
+        );
+        assert!(file.bookmarks.is_empty());
+        assert_eq!(
+            file.unparsed,
+            vec![
+                "[2023/01/15 18:30] Bookmark (\"match\" at soon)".to_string(),
+                "Recording started".to_string(),
+            ]
+        );
+    }
+}
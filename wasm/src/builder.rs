@@ -0,0 +1,428 @@
+use crate::header::interval_per_tick;
+use crate::packets::HEADER_SIZE;
+use crate::patch_header;
+use bitbuffer::{BitRead, BitWrite, BitWriteStream, LittleEndian};
+use std::collections::BTreeMap;
+use std::iter::once;
+use tf_demo_parser::demo::data::userinfo::PlayerInfo;
+use tf_demo_parser::demo::data::{DemoTick, MaybeUtf8String, ServerTick, UserInfo};
+use tf_demo_parser::demo::gamevent::GameEvent;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::gameevent::GameEventMessage;
+use tf_demo_parser::demo::message::packetentities::{
+    EntityId, PacketEntitiesMessage, PacketEntity, UpdateType,
+};
+use tf_demo_parser::demo::message::stringtable::UpdateStringTableMessage;
+use tf_demo_parser::demo::message::usermessage::{ChatMessageKind, SayText2Message, UserMessage};
+use tf_demo_parser::demo::message::{Message, NetTickMessage};
+use tf_demo_parser::demo::packet::datatable::ClassId;
+use tf_demo_parser::demo::packet::message::MessagePacket;
+use tf_demo_parser::demo::packet::stop::StopPacket;
+use tf_demo_parser::demo::packet::{Packet, PacketType};
+use tf_demo_parser::demo::parser::{DemoHandler, Encode, NullHandler, RawPacketStream};
+use tf_demo_parser::demo::sendprop::{SendProp, SendPropIdentifier, SendPropValue};
+use tf_demo_parser::{Demo, ParseError};
+
+/// The highest serial number that fits in the 10 bits it's sent with
+const MAX_SERIAL: u32 = (1 << 10) - 1;
+
+/// The messages for a single tick
+#[derive(Default)]
+struct Frame {
+    entities: Vec<PacketEntity>,
+    removed_entities: Vec<EntityId>,
+    messages: Vec<Message<'static>>,
+}
+
+/// Build a small demo from a script of ticks
+///
+/// The header, data tables, string tables and the rest of the signon are taken from a template demo,
+/// the packets after the signon are generated from the ticks added to the builder.
+/// `tests/data/template.dem`, generated by `cargo run --example make_template`, has a world and player class,
+/// the `userinfo` table and the definitions of the game events the editor looks at.
+///
+/// ```
+/// # use edit::DemoBuilder;
+/// # use tf_demo_parser::demo::sendprop::{SendPropIdentifier, SendPropValue};
+/// # fn main() -> Result<(), tf_demo_parser::ParseError> {
+/// let template = std::fs::read("tests/data/template.dem").unwrap();
+/// let team = SendPropIdentifier::new("DT_BaseEntity", "m_iTeamNum");
+/// let mut builder = DemoBuilder::from_template(&template)?;
+/// builder
+///     .tick(10u32.into())
+///     .enter(2u32.into(), "CTFPlayer", [(team, SendPropValue::Integer(2))])
+///     .player(2u32.into(), 10, "player", "[U:1:1234]")
+///     .chat(2u32.into(), "player", "hello")
+///     .tick(20u32.into())
+///     .delete(2u32.into());
+/// let demo = builder.build();
+/// # Ok(())
+/// # }
+/// ```
+pub struct DemoBuilder<'a> {
+    header: Header,
+    handler: DemoHandler<'a, NullHandler>,
+    signon: Vec<Packet<'a>>,
+    frames: BTreeMap<DemoTick, Frame>,
+    current: DemoTick,
+    /// The server class and serial number of the entities created by the builder
+    entities: BTreeMap<EntityId, (ClassId, u32)>,
+    last_serial: u32,
+    /// The index of the `userinfo` string table
+    user_table: Option<u8>,
+    /// The server tick of demo tick 0
+    first_server_tick: u32,
+}
+
+impl<'a> DemoBuilder<'a> {
+    /// Start a demo with the header and signon from the template demo
+    pub fn from_template(template: &'a [u8]) -> Result<Self, ParseError> {
+        let demo = Demo::new(template);
+        let mut stream = demo.get_stream();
+        let header = Header::read(&mut stream)?;
+        let mut packets = RawPacketStream::new(stream);
+        let mut handler = DemoHandler::default();
+        handler.handle_header(&header);
+
+        let mut signon = Vec::new();
+        let mut table_count = 0;
+        let mut user_table = None;
+        while let Some(packet) = packets.next(&handler.state_handler)? {
+            match &packet {
+                Packet::Message(_) | Packet::Stop(_) => break,
+                Packet::ConsoleCmd(_) | Packet::UserCmd(_) => continue,
+                Packet::Signon(packet) => {
+                    for message in &packet.messages {
+                        if let Message::CreateStringTable(message) = message {
+                            if message.table.name == "userinfo" {
+                                user_table = Some(table_count);
+                            }
+                            table_count += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            signon.push(packet.clone());
+            handler.handle_packet(packet)?;
+        }
+
+        Ok(DemoBuilder {
+            header,
+            handler,
+            signon,
+            frames: BTreeMap::new(),
+            current: DemoTick::default(),
+            entities: BTreeMap::new(),
+            last_serial: 0,
+            user_table,
+            first_server_tick: 1,
+        })
+    }
+
+    /// Set the server tick of demo tick 0, defaults to 1
+    ///
+    /// Demos of the same match recorded from different points in time share the server ticks
+    pub fn first_server_tick(&mut self, tick: ServerTick) -> &mut Self {
+        self.first_server_tick = u32::from(tick).max(1);
+        self
+    }
+
+    /// Add the following updates and messages at `tick`, a frame is written for the tick even if nothing is added
+    pub fn tick(&mut self, tick: DemoTick) -> &mut Self {
+        self.current = tick;
+        self.frames.entry(tick).or_default();
+        self
+    }
+
+    fn frame(&mut self) -> &mut Frame {
+        self.frames.entry(self.current).or_default()
+    }
+
+    /// Create an entity of the server class with the given name
+    ///
+    /// Every new entity gets a new serial number, an entity entering the PVS again after `leave` keeps its serial.
+    ///
+    /// Panics if the template has no server class with the name, or the class has none of the props.
+    pub fn enter(
+        &mut self,
+        entity: EntityId,
+        class: &str,
+        props: impl IntoIterator<Item = (SendPropIdentifier, SendPropValue)>,
+    ) -> &mut Self {
+        let server_class = self
+            .handler
+            .state_handler
+            .server_classes
+            .iter()
+            .find(|server_class| server_class.name.as_str() == class)
+            .unwrap_or_else(|| panic!("the template has no server class {}", class))
+            .id;
+        let serial = match self.entities.get(&entity) {
+            Some((existing_class, serial)) if *existing_class == server_class => *serial,
+            _ => {
+                // the server never hands out serial 0
+                self.last_serial = self.last_serial % MAX_SERIAL + 1;
+                self.last_serial
+            }
+        };
+        self.entities.insert(entity, (server_class, serial));
+        let props = self.props(server_class, props);
+        self.push_entity(entity, UpdateType::Enter, props);
+        self
+    }
+
+    /// Update the props of an entity created with `enter`
+    ///
+    /// Panics if the entity wasn't created by the builder, or its class has none of the props.
+    pub fn update(
+        &mut self,
+        entity: EntityId,
+        props: impl IntoIterator<Item = (SendPropIdentifier, SendPropValue)>,
+    ) -> &mut Self {
+        let (server_class, _) = self.entity(entity);
+        let props = self.props(server_class, props);
+        self.push_entity(entity, UpdateType::Preserve, props);
+        self
+    }
+
+    /// Move an entity out of the PVS without deleting it, use `enter` to bring it back into the PVS
+    pub fn leave(&mut self, entity: EntityId) -> &mut Self {
+        self.push_entity(entity, UpdateType::Leave, Vec::new());
+        self
+    }
+
+    /// Delete an entity while it's in the PVS
+    pub fn delete(&mut self, entity: EntityId) -> &mut Self {
+        self.push_entity(entity, UpdateType::Delete, Vec::new());
+        self.entities.remove(&entity);
+        self
+    }
+
+    /// Delete an entity that already left the PVS, through the removed entities of the entity message
+    ///
+    /// Removed entities are only sent with delta updates, `build` panics if they're added to the first tick.
+    pub fn remove(&mut self, entity: EntityId) -> &mut Self {
+        self.entities.remove(&entity);
+        self.frame().removed_entities.push(entity);
+        self
+    }
+
+    /// Add a player to the `userinfo` string table, `entity` is the entity id of the player
+    ///
+    /// Like the game, the friends id is set to the account id of a `[U:1:<account id>]` steam id.
+    /// Panics if the template has no `userinfo` table.
+    pub fn player(
+        &mut self,
+        entity: EntityId,
+        user_id: u16,
+        name: &str,
+        steam_id: &str,
+    ) -> &mut Self {
+        let table_id = self.user_table.expect("the template has no userinfo table");
+        let user = UserInfo {
+            entity_id: entity,
+            player_info: PlayerInfo {
+                name: name.into(),
+                user_id: user_id.into(),
+                steam_id: steam_id.into(),
+                friends_id: steam_id
+                    .strip_prefix("[U:1:")
+                    .and_then(|account| account.strip_suffix(']'))
+                    .and_then(|account| account.parse().ok())
+                    .unwrap_or_default(),
+                ..PlayerInfo::default()
+            },
+        };
+        let mut entry = user
+            .encode_to_string_table()
+            .expect("player info can always be encoded");
+        // the entries are indexed by the entity id minus one
+        let index = u32::from(entity) as u16 - 1;
+        entry.text = Some(index.to_string().into());
+        self.message(Message::UpdateStringTable(UpdateStringTableMessage {
+            entries: vec![(index, entry)],
+            table_id,
+        }))
+    }
+
+    /// Add a chat message from the player with the given entity id
+    pub fn chat(&mut self, client: EntityId, name: &str, text: &str) -> &mut Self {
+        self.message(Message::UserMessage(UserMessage::SayText2(Box::new(
+            SayText2Message {
+                client,
+                raw: 1,
+                kind: ChatMessageKind::ChatAll,
+                from: Some(MaybeUtf8String::from(name)),
+                text: MaybeUtf8String::from(text),
+            },
+        ))))
+    }
+
+    /// Add a game event
+    ///
+    /// Panics if the template doesn't define the event type.
+    pub fn event(&mut self, event: GameEvent) -> &mut Self {
+        let event_type = event.event_type();
+        let definition = self
+            .handler
+            .state_handler
+            .event_definitions
+            .iter()
+            .find(|definition| definition.event_type == event_type)
+            .unwrap_or_else(|| panic!("the template doesn't define {:?}", event_type));
+        let message = Message::GameEvent(GameEventMessage {
+            event_type_id: definition.id,
+            event_type,
+            event,
+        });
+        self.message(message)
+    }
+
+    /// Add any other message
+    pub fn message(&mut self, message: Message<'static>) -> &mut Self {
+        self.frame().messages.push(message);
+        self
+    }
+
+    fn entity(&self, entity: EntityId) -> (ClassId, u32) {
+        *self
+            .entities
+            .get(&entity)
+            .unwrap_or_else(|| panic!("entity {} wasn't created by the builder", entity))
+    }
+
+    fn props(
+        &self,
+        server_class: ClassId,
+        props: impl IntoIterator<Item = (SendPropIdentifier, SendPropValue)>,
+    ) -> Vec<SendProp> {
+        let send_table = &self.handler.state_handler.send_tables[usize::from(server_class)];
+        props
+            .into_iter()
+            .map(|(identifier, value)| {
+                let index = send_table
+                    .flattened_props
+                    .iter()
+                    .position(|definition| definition.identifier == identifier)
+                    .unwrap_or_else(|| panic!("{} has no prop {}", send_table.name, identifier));
+                SendProp {
+                    index: index as u32,
+                    identifier,
+                    value,
+                }
+            })
+            .collect()
+    }
+
+    fn push_entity(
+        &mut self,
+        entity_index: EntityId,
+        update_type: UpdateType,
+        props: Vec<SendProp>,
+    ) {
+        let (server_class, serial_number) = self.entity(entity_index);
+        self.frame().entities.push(PacketEntity {
+            server_class,
+            entity_index,
+            props,
+            in_pvs: update_type != UpdateType::Leave && update_type != UpdateType::Delete,
+            update_type,
+            serial_number,
+            delay: None,
+            delta: None,
+            baseline_index: 0,
+        });
+    }
+
+    /// Write the demo
+    ///
+    /// Panics if entities are removed on the first tick, there is no previous tick to send them as a delta to.
+    pub fn build(self) -> Vec<u8> {
+        let mut out_buffer = Vec::new();
+        let mut header = self.header.clone();
+        {
+            let mut out_stream = BitWriteStream::new(&mut out_buffer, LittleEndian);
+            header.write(&mut out_stream).unwrap();
+
+            let mut handler = DemoHandler::default();
+            handler.handle_header(&header);
+
+            for packet in self.signon {
+                packet
+                    .encode(&mut out_stream, &handler.state_handler)
+                    .unwrap();
+                handler.handle_packet(packet).unwrap();
+            }
+            header.signon = (out_stream.bit_len() / 8 - HEADER_SIZE) as u32;
+
+            let mut last_delta: Option<ServerTick> = None;
+            let mut max_entries = 0;
+            let mut last_tick = DemoTick::default();
+            let frame_count = self.frames.len();
+            for (tick, mut frame) in self.frames {
+                // the server ticks only have to be increasing, they start at 1 or more so every tick has a valid delta
+                let server_tick = ServerTick::from(u32::from(tick) + self.first_server_tick);
+                frame.entities.sort_by_key(|entity| entity.entity_index);
+                max_entries = frame
+                    .entities
+                    .iter()
+                    .map(|entity| u32::from(entity.entity_index) as u16 + 1)
+                    .chain(once(max_entries))
+                    .max()
+                    .unwrap_or_default();
+
+                assert!(
+                    last_delta.is_some() || frame.removed_entities.is_empty(),
+                    "entities can't be removed on the first tick"
+                );
+                let entities = PacketEntitiesMessage {
+                    entities: frame.entities,
+                    removed_entities: frame.removed_entities,
+                    max_entries,
+                    delta: last_delta,
+                    base_line: 0,
+                    updated_base_line: false,
+                };
+                let messages = [
+                    Message::NetTick(NetTickMessage {
+                        tick: server_tick,
+                        frame_time: 1881,
+                        std_dev: 263,
+                    }),
+                    Message::PacketEntities(entities),
+                ]
+                .into_iter()
+                .chain(frame.messages)
+                .collect();
+                let packet = Packet::Message(MessagePacket {
+                    tick,
+                    messages,
+                    ..MessagePacket::default()
+                });
+                packet
+                    .encode(&mut out_stream, &handler.state_handler)
+                    .unwrap();
+                handler.handle_packet(packet).unwrap();
+
+                last_delta = Some(server_tick);
+                last_tick = tick;
+            }
+
+            PacketType::Stop.write(&mut out_stream).unwrap();
+            StopPacket { tick: last_tick }
+                .encode(&mut out_stream, &handler.state_handler)
+                .unwrap();
+
+            let interval = match handler.state_handler.demo_meta.interval_per_tick {
+                interval if interval > 0.0 => interval,
+                _ => interval_per_tick(&self.header),
+            };
+            header.frames = frame_count as u32;
+            header.ticks = last_tick.into();
+            header.duration = header.ticks as f32 * interval;
+        }
+        patch_header(&mut out_buffer, &header);
+        out_buffer
+    }
+}
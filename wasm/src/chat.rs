@@ -0,0 +1,178 @@
+use crate::mutate::{MutatorList, PacketMutator};
+use crate::users::UserTracker;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::MaybeUtf8String;
+use tf_demo_parser::demo::message::packetentities::EntityId;
+use tf_demo_parser::demo::message::usermessage::{ChatMessageKind, HudTextLocation, UserMessage};
+use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::packet::Packet;
+use tf_demo_parser::ParserState;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatChannel {
+    All,
+    Team,
+    Dead,
+    Spectator,
+    /// Chat messages sent by the server or plugins
+    Server,
+}
+
+/// Select chat messages to remove
+///
+/// Empty `channels` or `players` match any channel or player.
+/// When `words` is set, those words are masked in the selected messages instead of removing them.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ChatFilterOptions {
+    #[serde(default)]
+    pub channels: Vec<ChatChannel>,
+    /// Steam ids of the players whose messages are selected, messages from the server never match
+    #[serde(default)]
+    pub players: Vec<String>,
+    #[serde(default)]
+    pub words: Vec<String>,
+}
+
+/// The chat filter, following the players to match the senders by steam id
+struct ChatFilter {
+    options: ChatFilterOptions,
+    /// The lowercase words to mask
+    words: Vec<String>,
+    users: UserTracker,
+}
+
+impl ChatFilter {
+    fn matches(&self, channels: &[ChatChannel], client: Option<EntityId>) -> bool {
+        let options = &self.options;
+        let channel_matches = options.channels.is_empty()
+            || channels
+                .iter()
+                .any(|channel| options.channels.contains(channel));
+        let player_matches = options.players.is_empty()
+            || client
+                .and_then(|client| self.users.steam_id(client))
+                .map(|steam_id| options.players.contains(&steam_id))
+                .unwrap_or_default();
+        channel_matches && player_matches
+    }
+
+    fn matches_message(&self, message: &UserMessage) -> bool {
+        match message {
+            UserMessage::SayText2(message) => {
+                let channels: &[ChatChannel] = match message.kind {
+                    ChatMessageKind::ChatAll => &[ChatChannel::All],
+                    ChatMessageKind::ChatTeam => &[ChatChannel::Team],
+                    ChatMessageKind::ChatAllDead => &[ChatChannel::All, ChatChannel::Dead],
+                    ChatMessageKind::ChatTeamDead => &[ChatChannel::Team, ChatChannel::Dead],
+                    ChatMessageKind::ChatAllSpec => &[ChatChannel::Spectator],
+                    ChatMessageKind::NameChange | ChatMessageKind::Empty => return false,
+                };
+                self.matches(channels, Some(message.client))
+            }
+            UserMessage::Text(message) if message.location == HudTextLocation::PrintTalk => {
+                self.matches(&[ChatChannel::Server], None)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl PacketMutator for ChatFilter {
+    fn mutate_packet(&self, packet: &mut Packet, _state: &ParserState) {
+        self.users.handle_packet(packet);
+        if let Packet::Message(packet) | Packet::Signon(packet) = packet {
+            if self.words.is_empty() {
+                packet.messages.retain(|message| match message {
+                    Message::UserMessage(message) => !self.matches_message(message),
+                    _ => true,
+                });
+            } else {
+                for message in packet.messages.iter_mut() {
+                    if let Message::UserMessage(message) = message {
+                        if self.matches_message(message) {
+                            match message {
+                                UserMessage::SayText2(message) => {
+                                    mask_words(&mut message.text, &self.words)
+                                }
+                                UserMessage::Text(message) => {
+                                    mask_words(&mut message.text, &self.words)
+                                }
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn observe_packet(&self, packet: &Packet, _state: &ParserState) {
+        self.users.handle_packet(packet);
+    }
+}
+
+/// Remove or censor chat messages
+pub fn filter_chat(mutators: &mut MutatorList, options: ChatFilterOptions) {
+    let words = options
+        .words
+        .iter()
+        .map(|word| word.to_lowercase())
+        .collect();
+    mutators.push_packet_mutator(ChatFilter {
+        options,
+        words,
+        users: UserTracker::default(),
+    });
+}
+
+fn mask_words(text: &mut MaybeUtf8String, words: &[String]) {
+    if let MaybeUtf8String::Valid(text) = text {
+        *text = text
+            .split_inclusive(|c: char| !c.is_alphanumeric())
+            .map(|part| {
+                let word = part.trim_end_matches(|c: char| !c.is_alphanumeric());
+                if words.contains(&word.to_lowercase()) {
+                    "*".repeat(word.chars().count()) + &part[word.len()..]
+                } else {
+                    part.to_string()
+                }
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(text: &str, words: &[&str]) -> String {
+        let words: Vec<String> = words.iter().map(|word| word.to_lowercase()).collect();
+        let mut text = MaybeUtf8String::from(text);
+        mask_words(&mut text, &words);
+        text.to_string()
+    }
+
+    #[test]
+    fn masks_whole_words() {
+        assert_eq!(mask("you are bad", &["bad"]), "you are ***");
+        assert_eq!(mask("badly played", &["bad"]), "badly played");
+        assert_eq!(mask("bad, bad!", &["bad"]), "***, ***!");
+    }
+
+    #[test]
+    fn masks_ignoring_case() {
+        assert_eq!(mask("BAD Bad bad", &["Bad"]), "*** *** ***");
+    }
+
+    #[test]
+    fn masks_by_characters() {
+        assert_eq!(mask("héllo there", &["héllo"]), "***** there");
+    }
+
+    #[test]
+    fn keeps_text_without_words() {
+        assert_eq!(mask("good game", &["bad"]), "good game");
+        assert_eq!(mask("", &["bad"]), "");
+    }
+}
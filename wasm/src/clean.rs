@@ -1,16 +1,26 @@
-use crate::missing_preserve::RemoveInvalidPreserveEntity;
+use crate::missing_preserve::FixEntityConsistency;
 use crate::mutate::MutatorList;
 use tf_demo_parser::demo::message::usermessage::UserMessageType;
-use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::message::{Message, MessageType};
 
 /// General cleanup we always want to do
 pub fn clean_demo(mutators: &mut MutatorList) {
-    mutators.push_message_filter(|message: &Message| {
+    mutators.push_message_mutator(FixEntityConsistency::new());
+}
+
+/// Remove all net messages and user messages of the given types
+pub fn remove_messages(
+    mutators: &mut MutatorList,
+    messages: Vec<MessageType>,
+    user_messages: Vec<UserMessageType>,
+) {
+    mutators.push_message_filter(move |message: &Message| {
         if let Message::UserMessage(usr_message) = message {
-            UserMessageType::CloseCaption != usr_message.message_type()
+            !user_messages
+                .iter()
+                .any(|ty| *ty == usr_message.message_type())
         } else {
-            true
+            !messages.contains(&message.get_message_type())
         }
     });
-    mutators.push_message_mutator(RemoveInvalidPreserveEntity::new());
 }
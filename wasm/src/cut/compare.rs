@@ -0,0 +1,275 @@
+use crate::cut::entity::ActiveEntities;
+use bitbuffer::BitRead;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::packetentities::{EntityId, PacketEntity, UpdateType};
+use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::packet::{Packet, PacketType};
+use tf_demo_parser::demo::parser::{DemoHandler, NullHandler, RawPacketStream};
+use tf_demo_parser::demo::sendprop::{SendPropIdentifier, SendPropValue};
+use tf_demo_parser::{Demo, ParserState};
+
+/// Stop comparing after this many ticks with divergences, later ticks usually repeat the same differences
+const MAX_DIVERGENT_TICKS: usize = 16;
+
+/// A difference between the entity state of the original and edited demo
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Divergence {
+    /// The entity exists in the original demo but not in the edited one
+    MissingEntity { entity: EntityId, class: String },
+    /// The entity exists in the edited demo but not in the original one
+    ExtraEntity { entity: EntityId, class: String },
+    ServerClass {
+        entity: EntityId,
+        original: String,
+        edited: String,
+    },
+    /// A prop value differs, `None` if the prop isn't set at all
+    Prop {
+        entity: EntityId,
+        prop: String,
+        original: Option<String>,
+        edited: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TickDivergence {
+    /// The tick in the edited demo
+    pub tick: DemoTick,
+    /// The matching tick in the original demo
+    pub original_tick: DemoTick,
+    pub divergences: Vec<Divergence>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct StateComparison {
+    /// The number of ticks the states were compared at
+    pub ticks: u32,
+    /// The first ticks where the states differ
+    pub divergences: Vec<TickDivergence>,
+}
+
+impl StateComparison {
+    pub fn matches(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+/// Replays a demo, tracking the entities the same way the cut does
+struct Replay<'a> {
+    packets: RawPacketStream<'a>,
+    handler: DemoHandler<'a, NullHandler>,
+    entities: ActiveEntities,
+    next: Option<Packet<'a>>,
+    /// Entities updated since the last comparison
+    touched: BTreeSet<EntityId>,
+}
+
+impl<'a> Replay<'a> {
+    fn new(demo: &'a Demo<'a>) -> Self {
+        let mut stream = demo.get_stream();
+        let header = Header::read(&mut stream).unwrap();
+        let mut handler = DemoHandler::default();
+        handler.handle_header(&header);
+        let mut packets = RawPacketStream::new(stream);
+        let next = packets.next(&handler.state_handler).unwrap();
+        Replay {
+            packets,
+            handler,
+            entities: ActiveEntities::default(),
+            next,
+            touched: BTreeSet::new(),
+        }
+    }
+
+    fn state(&self) -> &ParserState {
+        &self.handler.state_handler
+    }
+
+    fn next_tick(&self) -> Option<DemoTick> {
+        self.next.as_ref().map(Packet::tick)
+    }
+
+    /// Handle the next packet, returns the type of the handled packet
+    fn step(&mut self) -> Option<PacketType> {
+        let packet = self.next.take()?;
+        let packet_type = packet.packet_type();
+        if let Packet::Message(message_packet) | Packet::Signon(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::PacketEntities(message) = message {
+                    self.entities.handle_message(
+                        message,
+                        &self.handler.state_handler,
+                        message_packet.tick,
+                    );
+                    self.touched.extend(
+                        message
+                            .entities
+                            .iter()
+                            .map(|entity| entity.entity_index)
+                            .chain(message.removed_entities.iter().copied()),
+                    );
+                }
+            }
+        }
+        self.handler.handle_packet(packet).unwrap();
+        self.next = self.packets.next(&self.handler.state_handler).unwrap();
+        Some(packet_type)
+    }
+
+    /// Handle all packets up to and including `tick`
+    fn advance_to(&mut self, tick: DemoTick) {
+        while self
+            .next_tick()
+            .map(|next| next <= tick)
+            .unwrap_or_default()
+        {
+            self.step();
+        }
+    }
+
+    fn class_name(&self, entity: &PacketEntity) -> String {
+        self.state()
+            .server_classes
+            .get(usize::from(entity.server_class))
+            .map(|class| class.name.as_str().to_string())
+            .unwrap_or_else(|| entity.server_class.to_string())
+    }
+
+    /// The value of a prop as the client sees it, falling back to the baseline for props that were never sent
+    fn prop_value(
+        &self,
+        entity: &PacketEntity,
+        prop: &SendPropIdentifier,
+    ) -> Option<SendPropValue> {
+        entity
+            .props
+            .iter()
+            .find(|existing| existing.identifier == *prop)
+            .map(|existing| existing.value.clone())
+            .or_else(|| {
+                PacketEntity {
+                    props: Vec::new(),
+                    update_type: UpdateType::Enter,
+                    ..entity.clone()
+                }
+                .props(self.state())
+                .find(|baseline| baseline.identifier == *prop)
+                .map(|baseline| baseline.value)
+            })
+    }
+}
+
+/// Compare the entity state of an edited demo against the original demo at every tick of the edited demo
+///
+/// Both demos are replayed side by side, tracking the entities the same way the cut does.
+/// `offset` is the tick in the original demo that tick 0 of the edited demo corresponds to,
+/// the start of the cut when the cut ticks are numbered from 0.
+pub fn compare_state(original: &[u8], edited: &[u8], offset: DemoTick) -> StateComparison {
+    let original_demo = Demo::new(original);
+    let edited_demo = Demo::new(edited);
+    let mut original = Replay::new(&original_demo);
+    let mut edited = Replay::new(&edited_demo);
+
+    let mut comparison = StateComparison::default();
+    let mut compare_tick: Option<DemoTick> = None;
+    loop {
+        // compare once all packets for a tick are handled
+        let next_tick = edited.next_tick();
+        if let Some(tick) = compare_tick.filter(|tick| next_tick != Some(*tick)) {
+            let original_tick = tick + u32::from(offset);
+            original.advance_to(original_tick);
+            comparison.ticks += 1;
+            let divergences = compare_entities(&original, &edited);
+            original.touched.clear();
+            edited.touched.clear();
+            compare_tick = None;
+
+            if !divergences.is_empty() {
+                comparison.divergences.push(TickDivergence {
+                    tick,
+                    original_tick,
+                    divergences,
+                });
+                if comparison.divergences.len() >= MAX_DIVERGENT_TICKS {
+                    break;
+                }
+            }
+        }
+
+        match edited.step() {
+            Some(PacketType::Message) => compare_tick = next_tick,
+            Some(_) => {}
+            None => break,
+        }
+    }
+    comparison
+}
+
+fn compare_entities(original: &Replay, edited: &Replay) -> Vec<Divergence> {
+    let original_ids = original.entities.entity_ids();
+    let edited_ids = edited.entities.entity_ids();
+    let mut divergences = Vec::new();
+
+    for entity in original_ids.difference(&edited_ids) {
+        if let Some(original_entity) = original.entities.get(*entity) {
+            divergences.push(Divergence::MissingEntity {
+                entity: *entity,
+                class: original.class_name(original_entity),
+            });
+        }
+    }
+    for entity in edited_ids.difference(&original_ids) {
+        if let Some(edited_entity) = edited.entities.get(*entity) {
+            divergences.push(Divergence::ExtraEntity {
+                entity: *entity,
+                class: edited.class_name(edited_entity),
+            });
+        }
+    }
+
+    // props only change for entities that got an update on either side
+    let touched: BTreeSet<EntityId> = original.touched.union(&edited.touched).copied().collect();
+    for entity in touched.intersection(&original_ids) {
+        let (Some(original_entity), Some(edited_entity)) =
+            (original.entities.get(*entity), edited.entities.get(*entity))
+        else {
+            continue;
+        };
+
+        let original_class = original.class_name(original_entity);
+        let edited_class = edited.class_name(edited_entity);
+        if original_class != edited_class {
+            divergences.push(Divergence::ServerClass {
+                entity: *entity,
+                original: original_class,
+                edited: edited_class,
+            });
+            continue;
+        }
+
+        let props: BTreeSet<SendPropIdentifier> = original_entity
+            .props
+            .iter()
+            .chain(edited_entity.props.iter())
+            .map(|prop| prop.identifier)
+            .collect();
+        for prop in &props {
+            let original_value = original.prop_value(original_entity, prop);
+            let edited_value = edited.prop_value(edited_entity, prop);
+            if original_value != edited_value {
+                divergences.push(Divergence::Prop {
+                    entity: *entity,
+                    prop: prop.to_string(),
+                    original: original_value.map(|value| value.to_string()),
+                    edited: edited_value.map(|value| value.to_string()),
+                });
+            }
+        }
+    }
+    divergences
+}
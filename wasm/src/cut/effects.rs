@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use tf_demo_parser::demo::message::packetentities::{EntityId, PacketEntitiesMessage, UpdateType};
+use tf_demo_parser::demo::message::tempentities::{EventInfo, TempEntitiesMessage};
+use tf_demo_parser::demo::message::voice::ParseSoundsMessage;
+use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::packet::stringtable::{StringTable, StringTableEntry};
+use tf_demo_parser::demo::sendprop::{SendPropIdentifier, SendPropValue};
+use tf_demo_parser::ParserState;
+
+use crate::sounds::{
+    is_looping_sound, read_sounds, sounds_message, SoundFormat, SoundInfo, SND_STOP,
+    SND_STOP_LOOPING,
+};
+
+/// Sound channels that can play multiple sounds for the same entity at once
+const SHARED_CHANNELS: &[u32] = &[0, 6];
+/// Particle attachments that stay attached to the entity, other attachments are one-off effects
+const FOLLOW_ATTACHMENTS: &[i64] = &[1, 4, 6];
+const MAX_PARTICLES_PER_ENTITY: usize = 8;
+const MAX_DECALS: usize = 128;
+
+/// Sounds and effects that are still active, which need to be started again at the cut start
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct ActiveEffects {
+    sound_table: Option<u8>,
+    /// Precached sounds that keep playing until they're stopped
+    looping_sounds: BTreeSet<u32>,
+    sounds: Vec<SoundInfo>,
+    /// Particle effects attached to an entity, by entity index
+    particles: BTreeMap<u32, Vec<EventInfo>>,
+    /// The last spray of every player
+    sprays: BTreeMap<i64, EventInfo>,
+    decals: VecDeque<EventInfo>,
+}
+
+impl ActiveEffects {
+    /// `table_count` is the number of tables that exist before the message is handled
+    pub fn handle_message(&mut self, message: &Message, table_count: usize, state: &ParserState) {
+        match message {
+            Message::CreateStringTable(msg) => self.handle_table(table_count, &msg.table),
+            Message::UpdateStringTable(msg) if Some(msg.table_id) == self.sound_table => {
+                self.handle_sound_entries(&msg.entries)
+            }
+            Message::ParseSounds(msg) => self.handle_sounds(msg, state),
+            Message::TempEntities(msg) => self.handle_temp_entities(msg, state),
+            Message::PacketEntities(msg) => self.handle_entities(msg),
+            _ => {}
+        }
+    }
+
+    pub fn handle_table(&mut self, table_id: usize, table: &StringTable) {
+        if table.name == "soundprecache" {
+            self.sound_table = Some(table_id as u8);
+            self.handle_sound_entries(&table.entries);
+        }
+    }
+
+    fn handle_sound_entries(&mut self, entries: &[(u16, StringTableEntry)]) {
+        for (index, entry) in entries {
+            if let Some(path) = entry.text.as_deref() {
+                if is_looping_sound(path) {
+                    self.looping_sounds.insert(*index as u32);
+                } else {
+                    self.looping_sounds.remove(&(*index as u32));
+                }
+            }
+        }
+    }
+
+    fn handle_sounds(&mut self, message: &ParseSoundsMessage, state: &ParserState) {
+        let format = SoundFormat::new(state.protocol_version);
+        let sounds = match read_sounds(message, &format) {
+            Some(sounds) => sounds,
+            None => return,
+        };
+        for sound in sounds {
+            // sentences are indexes into the sentence list, not the precache table
+            if sound.sentence {
+                continue;
+            }
+            if sound.flags & SND_STOP_LOOPING != 0 {
+                self.sounds.retain(|active| active.entity != sound.entity);
+            }
+            if sound.flags & SND_STOP != 0 {
+                self.sounds
+                    .retain(|active| active.entity != sound.entity || active.sound != sound.sound);
+                continue;
+            }
+            if !SHARED_CHANNELS.contains(&sound.channel) {
+                self.sounds.retain(|active| {
+                    active.entity != sound.entity || active.channel != sound.channel
+                });
+            }
+            self.sounds
+                .retain(|active| active.entity != sound.entity || active.sound != sound.sound);
+            if self.looping_sounds.contains(&sound.sound) {
+                self.sounds.push(sound);
+            }
+        }
+    }
+
+    fn handle_temp_entities(&mut self, message: &TempEntitiesMessage, state: &ParserState) {
+        for event in &message.events {
+            let class = state
+                .server_classes
+                .get(usize::from(event.class_id))
+                .map(|class| class.name.as_str());
+            let event = EventInfo {
+                fire_delay: 0.0,
+                reliable: false,
+                ..event.clone()
+            };
+            match class {
+                Some("CTETFParticleEffect") => self.handle_particle(event),
+                Some("CTEPlayerDecal") => {
+                    let player = int_prop(&event, "DT_TEPlayerDecal", "m_nPlayer");
+                    self.sprays.insert(player, event);
+                }
+                Some("CTEWorldDecal" | "CTEBSPDecal" | "CTEProjectedDecal") => {
+                    if self.decals.len() >= MAX_DECALS {
+                        self.decals.pop_front();
+                    }
+                    self.decals.push_back(event);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn handle_particle(&mut self, event: EventInfo) {
+        let entity = int_prop(&event, "DT_TETFParticleEffect", "entindex");
+        let attachment = int_prop(&event, "DT_TETFParticleEffect", "m_iAttachType");
+        if int_prop(&event, "DT_TETFParticleEffect", "m_bResetParticles") != 0 {
+            self.particles.remove(&(entity as u32));
+        }
+        if entity > 0 && FOLLOW_ATTACHMENTS.contains(&attachment) {
+            let particles = self.particles.entry(entity as u32).or_default();
+            if particles.len() >= MAX_PARTICLES_PER_ENTITY {
+                particles.remove(0);
+            }
+            particles.push(event);
+        }
+    }
+
+    fn handle_entities(&mut self, message: &PacketEntitiesMessage) {
+        let deleted = message
+            .entities
+            .iter()
+            .filter(|entity| entity.update_type == UpdateType::Delete)
+            .map(|entity| entity.entity_index)
+            .chain(message.removed_entities.iter().copied());
+        for entity in deleted {
+            let entity = u32::from(entity);
+            self.particles.remove(&entity);
+            self.sounds.retain(|active| active.entity != entity);
+        }
+    }
+
+    /// Messages that start the active sounds and effects for the entities that exist at the cut start
+    pub fn encode(
+        &self,
+        entities: &BTreeSet<EntityId>,
+        state: &ParserState,
+    ) -> Vec<Message<'static>> {
+        let exists = |entity: i64| {
+            entity == 0 || (entity > 0 && entities.contains(&EntityId::from(entity as u32)))
+        };
+
+        let sounds: Vec<SoundInfo> = self
+            .sounds
+            .iter()
+            .filter(|sound| exists(sound.entity as i64))
+            .map(SoundInfo::restarted)
+            .collect();
+        let format = SoundFormat::new(state.protocol_version);
+        let sound_messages = sounds.chunks(u8::MAX as usize).filter_map(|sounds| {
+            let message = sounds_message(sounds, &format);
+            if message.is_none() {
+                log::warn!("failed to encode the active sounds at the start of the cut");
+            }
+            message.map(Message::ParseSounds)
+        });
+
+        let decals = self
+            .sprays
+            .values()
+            .chain(self.decals.iter())
+            .filter(|event| {
+                exists(int_prop(event, "DT_TEPlayerDecal", "m_nEntity"))
+                    && exists(int_prop(event, "DT_TEBSPDecal", "m_nEntity"))
+            });
+        let particles = self
+            .particles
+            .iter()
+            .filter(|(entity, _)| exists(**entity as i64))
+            .flat_map(|(_, particles)| particles.iter());
+        let events: Vec<EventInfo> = decals.chain(particles).cloned().collect();
+        let event_messages = events.chunks(u8::MAX as usize).map(|events| {
+            Message::TempEntities(TempEntitiesMessage {
+                events: events.to_vec(),
+            })
+        });
+
+        event_messages.chain(sound_messages).collect()
+    }
+}
+
+/// Get an integer prop from the event, props that aren't set default to 0
+fn int_prop(event: &EventInfo, table: &str, name: &str) -> i64 {
+    let identifier = SendPropIdentifier::new(table, name);
+    event
+        .props
+        .iter()
+        .find(|prop| prop.identifier == identifier)
+        .and_then(|prop| match &prop.value {
+            SendPropValue::Integer(value) => Some(*value),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet};
 
 use tf_demo_parser::demo::data::{DemoTick, ServerTick};
@@ -8,7 +9,7 @@ use tf_demo_parser::demo::packet::datatable::ClassId;
 use tf_demo_parser::demo::sendprop::SendPropIdentifier;
 use tf_demo_parser::ParserState;
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct ActiveEntities {
     entities: BTreeMap<EntityId, PacketEntity>,
     simulation_updates: BTreeMap<EntityId, DemoTick>,
@@ -80,10 +81,31 @@ impl ActiveEntities {
         }
     }
 
+    pub fn get(&self, entity_index: EntityId) -> Option<&PacketEntity> {
+        self.entities.get(&entity_index)
+    }
+
     pub fn entity_ids(&self) -> BTreeSet<EntityId> {
         self.entities.keys().copied().collect()
     }
 
+    /// An entity message that registers the class of every active entity
+    pub fn class_message(&self) -> PacketEntitiesMessage {
+        encode_entities(
+            self.entities
+                .values()
+                .map(|entity| PacketEntity {
+                    props: Vec::new(),
+                    update_type: UpdateType::Preserve,
+                    ..entity.clone()
+                })
+                .collect(),
+            self.max_entities,
+            None,
+            Vec::new(),
+        )
+    }
+
     pub fn baseline_ids(&self, state: &ParserState) -> BTreeSet<EntityId> {
         state.instance_baselines[0]
             .keys()
@@ -194,7 +216,7 @@ fn encode_entities(
     delta: Option<ServerTick>,
     removed_entities: Vec<EntityId>,
 ) -> PacketEntitiesMessage {
-    entities.sort_by(|a, b| a.entity_index.cmp(&b.entity_index));
+    entities.sort_by_key(|a| a.entity_index);
     PacketEntitiesMessage {
         entities,
         removed_entities,
@@ -0,0 +1,214 @@
+use crate::cut::{next_decoded, SkipState, PRESERVE_PACKETS};
+use crate::provenance::input_hash;
+use crate::{EditReport, SkippedPacket};
+use bitbuffer::BitRead;
+use serde::{Deserialize, Serialize};
+use std::mem::take;
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::packetentities::{PacketEntitiesMessage, UpdateType};
+use tf_demo_parser::demo::message::{Message, NetTickMessage};
+use tf_demo_parser::demo::packet::message::MessagePacket;
+use tf_demo_parser::demo::packet::{Packet, PacketType};
+use tf_demo_parser::demo::parser::state::Baseline;
+use tf_demo_parser::demo::parser::{DemoHandler, NullHandler, RawPacketStream};
+use tf_demo_parser::{Demo, ParseError};
+
+/// Roughly one snapshot per minute
+const DEFAULT_INTERVAL: u32 = 4000;
+/// Every snapshot holds the full entity state, for long demos the interval is increased to stay below this
+const MAX_SNAPSHOTS: usize = 32;
+
+/// Periodic snapshots of the state needed to start a cut, so cuts don't have to replay the demo from the start
+#[derive(Serialize, Deserialize, Clone)]
+pub struct SeekIndex {
+    input_hash: String,
+    interval: u32,
+    snapshots: Vec<Snapshot>,
+    /// Bit positions of the packets after the signon that have to be preserved, a snapshot can't skip over them
+    #[serde(default)]
+    late_preserved: Vec<usize>,
+    /// Packets that couldn't be decoded while building the index
+    #[serde(default)]
+    skipped: Vec<SkippedPacket>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub(super) struct Snapshot {
+    pub(super) tick: DemoTick,
+    /// Bit position of the packet following the snapshot
+    pub(super) position: usize,
+    state: SkipState,
+    baselines: [PacketEntitiesMessage; 2],
+}
+
+impl SeekIndex {
+    pub fn new(input: &[u8]) -> Result<Self, ParseError> {
+        Self::with_interval(input, DEFAULT_INTERVAL)
+    }
+
+    /// Build an index with a snapshot every `interval` ticks
+    ///
+    /// The interval is doubled whenever the demo would need more than `MAX_SNAPSHOTS` snapshots.
+    pub fn with_interval(input: &[u8], mut interval: u32) -> Result<Self, ParseError> {
+        let demo = Demo::new(input);
+        let mut stream = demo.get_stream();
+        let header = Header::read(&mut stream)?;
+
+        let mut packets = RawPacketStream::new(stream.clone());
+        let mut handler = DemoHandler::default();
+        handler.handle_header(&header);
+
+        let mut state = SkipState::default();
+        let mut snapshots = Vec::new();
+        let mut late_preserved = Vec::new();
+        let mut report = EditReport::default();
+        let mut next_snapshot = DemoTick::from(interval);
+        let mut signon_done = false;
+
+        // packets that can't be decoded are left out, the same way lenient edits skip them
+        loop {
+            let position = packets.pos();
+            let Some(packet) = next_decoded(
+                &mut packets,
+                &stream,
+                &handler.state_handler,
+                true,
+                &mut report,
+            )?
+            else {
+                break;
+            };
+            if PRESERVE_PACKETS.contains(&packet.packet_type()) {
+                if signon_done {
+                    late_preserved.push(position);
+                }
+                state.handle_preserved(&packet, &handler.state_handler);
+                handler.handle_packet(packet)?;
+            } else if packet.packet_type() != PacketType::ConsoleCmd {
+                signon_done = true;
+                state.handle_packet(&packet, &handler.state_handler);
+                let tick = packet.tick();
+                handler.handle_packet(packet)?;
+
+                if tick >= next_snapshot {
+                    snapshots.push(Snapshot {
+                        tick,
+                        position: packets.pos(),
+                        state: state.clone(),
+                        baselines: [
+                            baseline_message(&handler.state_handler.instance_baselines[0], 1),
+                            baseline_message(&handler.state_handler.instance_baselines[1], 0),
+                        ],
+                    });
+                    if snapshots.len() > MAX_SNAPSHOTS {
+                        // keep every other snapshot, so they stay evenly spaced
+                        let mut index = 0;
+                        snapshots.retain(|_| {
+                            index += 1;
+                            index % 2 == 0
+                        });
+                        interval *= 2;
+                    }
+                    next_snapshot = snapshots
+                        .last()
+                        .map(|snapshot| snapshot.tick)
+                        .unwrap_or(tick)
+                        + interval;
+                }
+            }
+        }
+
+        Ok(SeekIndex {
+            input_hash: input_hash(input),
+            interval,
+            snapshots,
+            late_preserved,
+            skipped: report.skipped,
+        })
+    }
+
+    /// Check if the index was created for this demo
+    pub fn matches(&self, input: &[u8]) -> bool {
+        self.input_hash == input_hash(input)
+    }
+
+    pub(super) fn snapshot_before(&self, tick: DemoTick) -> Option<&Snapshot> {
+        self.snapshots
+            .iter()
+            .take_while(|snapshot| snapshot.tick < tick)
+            .last()
+    }
+
+    /// The packets that a cut starting from a snapshot at `tick` skips without decoding them
+    pub(super) fn skipped_before(&self, tick: DemoTick) -> impl Iterator<Item = &SkippedPacket> {
+        self.skipped
+            .iter()
+            .take_while(move |skipped| skipped.tick <= tick)
+    }
+
+    /// Positions of the packets after the signon that have to be preserved, up to `position`
+    pub(super) fn late_preserved_before(
+        &self,
+        position: usize,
+    ) -> impl Iterator<Item = usize> + '_ {
+        self.late_preserved
+            .iter()
+            .copied()
+            .take_while(move |preserved| *preserved < position)
+    }
+}
+
+fn baseline_message(baseline: &Baseline, base_line: u8) -> PacketEntitiesMessage {
+    PacketEntitiesMessage {
+        entities: baseline
+            .clone()
+            .into_values()
+            .map(|mut entity| {
+                entity.update_type = UpdateType::Enter;
+                entity
+            })
+            .collect(),
+        base_line,
+        updated_base_line: true,
+        ..PacketEntitiesMessage::default()
+    }
+}
+
+impl Snapshot {
+    /// Bring the handler to the state it would have at the snapshot
+    pub(super) fn restore(
+        &self,
+        handler: &mut DemoHandler<NullHandler>,
+    ) -> Result<SkipState, ParseError> {
+        let state = self.state.clone();
+
+        let mut messages = vec![Message::NetTick(NetTickMessage {
+            tick: state.server_tick,
+            frame_time: 0,
+            std_dev: 0,
+        })];
+        let existing_tables = handler.state_handler.string_tables.len();
+        messages.extend(state.table_updates.clone().encode(existing_tables));
+        messages.push(Message::PacketEntities(state.entities.class_message()));
+
+        handler.handle_packet(Packet::Message(MessagePacket {
+            tick: self.tick,
+            messages,
+            ..MessagePacket::default()
+        }))?;
+
+        // a baseline update starts from a copy of the other baseline, so each baseline is
+        // built from empty baselines on its own instead of letting them mix
+        let baselines = self.baselines.clone().map(|baseline| {
+            let index = 1 - baseline.base_line as usize;
+            handler.state_handler.instance_baselines = Default::default();
+            handler
+                .state_handler
+                .handle_message(Message::PacketEntities(baseline), self.tick);
+            take(&mut handler.state_handler.instance_baselines[index])
+        });
+        handler.state_handler.instance_baselines = baselines;
+        Ok(state)
+    }
+}
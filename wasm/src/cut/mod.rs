@@ -1,27 +1,45 @@
+mod compare;
+mod effects;
 mod entity;
+mod index;
 mod string_tables;
 
+pub use compare::{compare_state, Divergence, StateComparison, TickDivergence};
+pub use index::SeekIndex;
+
 use bitbuffer::{BitRead, BitWrite, BitWriteStream, LittleEndian};
+use std::cell::{Cell, RefCell};
 use std::cmp::min;
 use std::collections::BTreeSet;
-use std::convert::TryInto;
 use std::iter::once;
 use std::mem::take;
+use std::rc::Rc;
 use tf_demo_parser::demo::header::Header;
 use tf_demo_parser::demo::message::packetentities::{EntityId, PacketEntitiesMessage, UpdateType};
 
+use serde::{Deserialize, Serialize};
 use tf_demo_parser::demo::data::{DemoTick, ServerTick};
 use tf_demo_parser::demo::message::{Message, NetTickMessage};
+use tf_demo_parser::demo::packet::consolecmd::ConsoleCmdPacket;
 use tf_demo_parser::demo::packet::message::MessagePacket;
 use tf_demo_parser::demo::packet::stop::StopPacket;
 use tf_demo_parser::demo::packet::{Packet, PacketType};
 use tf_demo_parser::demo::parser::{DemoHandler, Encode, NullHandler, RawPacketStream};
-use tf_demo_parser::{Demo, ParserState};
+use tf_demo_parser::{Demo, ParseError, ParserState, Stream};
 
+use crate::cut::effects::ActiveEffects;
 use crate::cut::entity::ActiveEntities;
 use crate::cut::string_tables::StringTablesUpdates;
-use crate::mutate::MessageMutator;
-use crate::{find_stv, EditOptions, PacketMutator};
+use crate::director::Director;
+use crate::idle::{find_idle, split_idle};
+use crate::mutate::MutatorList;
+use crate::options::TickNumbering;
+use crate::packets::{next_packet, NextPacket, PacketWriter};
+use crate::provenance::Provenance;
+use crate::report::EditReport;
+use crate::{
+    count_ticks, find_stv, parse_failed, patch_header, EditOptions, PacketMutator, TickRange,
+};
 
 const PRESERVE_PACKETS: &[PacketType] = &[
     PacketType::Signon,
@@ -30,26 +48,98 @@ const PRESERVE_PACKETS: &[PacketType] = &[
     PacketType::SyncTick,
 ];
 
-pub fn cut(input: &[u8], options: EditOptions) -> Vec<u8> {
+pub fn cut(
+    input: &[u8],
+    options: EditOptions,
+    index: Option<&SeekIndex>,
+) -> Result<(Vec<u8>, EditReport), String> {
     let mut out_buffer = Vec::with_capacity(input.len());
-    {
+    let mut report = EditReport::default();
+    let header = {
         let mut out_stream = BitWriteStream::new(&mut out_buffer, LittleEndian);
 
-        let demo = Demo::new(&input);
+        let demo = Demo::new(input);
         let spectator_id = find_stv(&demo).unwrap_or_else(|| EntityId::from(1u32));
         let mut stream = demo.get_stream();
         let mut header = Header::read(&mut stream).unwrap();
 
-        let mut mutators = options.as_mutator(spectator_id);
-        let start_tick = options.cut.unwrap().from;
-        let end_tick = options.cut.unwrap().to;
+        let mut mutators = options
+            .as_mutator(&demo, spectator_id)
+            .map_err(parse_failed)?;
+        let mut director = options
+            .auto_director
+            .as_ref()
+            .map(|director_options| Director::new(&demo, director_options))
+            .transpose()
+            .map_err(parse_failed)?;
+
+        // demos from crashed recordings have no tick count in the header
+        let demo_ticks = match header.ticks {
+            0 => count_ticks(input),
+            ticks => ticks,
+        };
+        let range = match options.cut {
+            Some(requested) => {
+                let start_tick = min(
+                    DemoTick::from(demo_ticks.saturating_sub(10)),
+                    requested.from,
+                );
+                let end_tick = min(DemoTick::from(demo_ticks), requested.to);
+                if start_tick != requested.from {
+                    report.adjust(format!(
+                        "Moved the start of the cut from tick {} to {}, the demo only has {} ticks",
+                        requested.from, start_tick, demo_ticks
+                    ));
+                }
+                if end_tick != requested.to {
+                    report.adjust(format!(
+                        "Moved the end of the cut from tick {} to {}, the demo only has {} ticks",
+                        requested.to, end_tick, demo_ticks
+                    ));
+                }
+                let range = TickRange {
+                    from: start_tick,
+                    to: end_tick,
+                };
+                report.cut = Some(range);
+                range
+            }
+            None => TickRange {
+                from: DemoTick::default(),
+                to: DemoTick::from(demo_ticks),
+            },
+        };
 
-        let start_tick = min(DemoTick::from(header.ticks - 10), start_tick);
-        let end_tick = min(DemoTick::from(header.ticks), end_tick);
-        let duration_per_tick = header.ticks as f32 / header.duration;
+        let mut removed_ranges = options.remove_ranges.clone();
+        if let Some(idle_options) = &options.remove_idle {
+            removed_ranges.extend(find_idle(&demo, idle_options).map_err(parse_failed)?);
+        }
+        removed_ranges.sort_by_key(|range| range.from);
+        let segments = if removed_ranges.is_empty() {
+            vec![range]
+        } else {
+            let (kept, removed) = split_idle(range, &removed_ranges);
+            if kept.is_empty() {
+                report.adjust(String::from(
+                    "Everything would be removed from the demo, not removing anything",
+                ));
+                vec![range]
+            } else {
+                report.removed = removed;
+                kept
+            }
+        };
+        let start_tick = segments[0].from;
+        let tick_base = match options.tick_numbering {
+            TickNumbering::Zero => 0,
+            TickNumbering::Original => start_tick.into(),
+            TickNumbering::Base(base) => base.into(),
+        };
 
-        header.ticks = (end_tick - start_tick).into();
-        header.duration = header.ticks as f32 * duration_per_tick;
+        let provenance = options
+            .embed_provenance
+            .then(|| Provenance::new(input, &header, &options, start_tick));
+        mutators.mutate_header(&mut header);
         header.write(&mut out_stream).unwrap();
 
         let mut packets = RawPacketStream::new(stream.clone());
@@ -59,153 +149,468 @@ pub fn cut(input: &[u8], options: EditOptions) -> Vec<u8> {
         let mut handler = DemoHandler::default();
         handler.handle_header(&header);
 
-        let start_state = skip_start(&mut start_handler, &mut packets, start_tick);
+        let mut writer = PacketWriter::new(&options, &[PacketType::ConsoleCmd]);
 
-        for mut packet in start_state.start_packets {
+        let StartState {
+            mut state,
+            start_packets,
+        } = skip_start(
+            &mut start_handler,
+            &mut packets,
+            &stream,
+            start_tick,
+            index,
+            options.lenient,
+            &mut report,
+        )
+        .map_err(parse_failed)?;
+
+        for mut packet in start_packets {
             mutators.mutate_packet(&mut packet, &handler.state_handler);
-            packet
-                .encode(&mut out_stream, &handler.state_handler)
-                .unwrap();
-            handler.handle_packet(packet).unwrap();
+            writer.encode(&packet, &mut out_stream, &handler.state_handler);
+            handler.handle_packet(packet).map_err(parse_failed)?;
+        }
+        for packet in provenance
+            .iter()
+            .flat_map(|provenance| provenance.packets(tick_base.into()))
+        {
+            writer.write(&packet, &mut out_stream, &handler.state_handler);
         }
-        let delta_tick = start_state.last_delta;
-
-        let start_entities = start_state.entities.entity_ids();
 
-        let string_table_updates = start_state
-            .table_updates
-            .encode()
-            .into_iter()
-            .map(|msg| Message::UpdateStringTable(msg));
-        let (baseline_updates, entity_update, removed_update) = start_state.entities.encode(
-            &start_handler.state_handler,
-            delta_tick - 2,
-            start_tick,
+        let sync = sync_packets(
+            &state,
             &start_handler.state_handler,
+            &handler.state_handler,
+            packets.clone(),
+            &BTreeSet::new(),
         );
-        let baseline_updates = baseline_updates.into_iter().map(Message::PacketEntities);
-        let start_packets = string_table_updates
-            .chain(baseline_updates)
-            .map(|msg| msg_packet(vec![net_tick(delta_tick - 2), msg]))
-            .chain(once(msg_packet(vec![
-                net_tick(delta_tick - 1),
-                Message::PacketEntities(entity_update),
-            ])))
-            .chain(once(Packet::Message(MessagePacket {
-                messages: vec![
-                    net_tick(delta_tick),
-                    Message::PacketEntities(removed_update),
-                ],
-                ..MessagePacket::default()
-            })));
-        for packet in start_packets {
-            packet
-                .encode(&mut out_stream, &handler.state_handler)
-                .unwrap();
-            handler.handle_packet(packet).unwrap();
-        }
+        let mut output = Output {
+            handler: &mut handler,
+            writer: &mut writer,
+            stream: &mut out_stream,
+            mutators: &mutators,
+        };
+        output
+            .write_sync(sync, tick_base.into())
+            .map_err(parse_failed)?;
 
-        // create the net ticks needed for later deltas
-        let fill_ticks = (delta_tick + 1)
-            .range_inclusive(start_state.server_tick)
-            .into_iter()
-            .map(|tick| net_tick(tick));
-        let fill_packets = fill_ticks.map(|msg| {
-            Packet::Message(MessagePacket {
-                messages: vec![
-                    msg,
-                    Message::PacketEntities(PacketEntitiesMessage {
-                        max_entries: start_state.entity_max,
-                        delta: Some((delta_tick - 1).try_into().unwrap()),
-                        ..PacketEntitiesMessage::default()
-                    }),
-                ],
-                ..MessagePacket::default()
-            })
-        });
-        for packet in fill_packets {
-            packet
-                .encode(&mut out_stream, &handler.state_handler)
-                .unwrap();
-        }
+        let delete_filter = Rc::new(DeleteFilter::new(
+            state.entities.entity_ids(),
+            state.server_tick,
+        ));
+        let filter = delete_filter.clone();
+        mutators.push_message_mutator(move |message: &mut Message| filter.filter_message(message));
 
-        mutators.push_message_mutator(DeleteFilter::new(start_entities, start_state.server_tick));
+        // ticks removed from the start of the demo and the idle periods so far
+        let offset = Rc::new(Cell::new(start_tick));
+        let packet_offset = offset.clone();
         mutators.push_packet_mutator(move |packet: &mut Packet| {
-            packet.set_tick(packet.tick() - start_tick)
+            packet.set_tick(shift_tick(packet.tick(), packet_offset.get(), tick_base))
         });
 
-        while let Some(mut packet) = packets.next(&handler.state_handler).unwrap() {
-            let original_tick = packet.tick();
+        let mut previous_end = start_tick;
+        for (i, segment) in segments.iter().enumerate() {
+            // the state only has to be tracked if we need to sync up again for the next segment
+            let track = i + 1 < segments.len();
 
-            mutators.mutate_packet(&mut packet, &handler.state_handler);
+            if i > 0 {
+                let previous_entities = state.entities.entity_ids();
+                skip_to(
+                    &mut state,
+                    &mut start_handler,
+                    &mut packets,
+                    &stream,
+                    segment.from,
+                    options.lenient,
+                    &mut report,
+                )
+                .map_err(parse_failed)?;
+                // split_idle only returns ordered segments with a gap between them
+                let skipped = u32::from(segment.from)
+                    .checked_sub(u32::from(previous_end) + 1)
+                    .ok_or_else(|| {
+                        format!(
+                            "The part of the demo starting at tick {} overlaps the part before it",
+                            segment.from
+                        )
+                    })?;
+                offset.set(offset.get() + skipped);
 
-            if packet.packet_type() != PacketType::ConsoleCmd {
-                packet
-                    .encode(&mut out_stream, &handler.state_handler)
-                    .unwrap();
+                let sync = sync_packets(
+                    &state,
+                    &start_handler.state_handler,
+                    &handler.state_handler,
+                    packets.clone(),
+                    &previous_entities,
+                );
+                let mut output = Output {
+                    handler: &mut handler,
+                    writer: &mut writer,
+                    stream: &mut out_stream,
+                    mutators: &mutators,
+                };
+                output
+                    .write_sync(sync, shift_tick(segment.from, offset.get(), tick_base))
+                    .map_err(parse_failed)?;
+                delete_filter.reset(state.entities.entity_ids(), state.server_tick);
             }
-            handler.handle_packet(packet).unwrap();
 
-            if original_tick >= end_tick {
-                break;
+            loop {
+                let next = next_packet(
+                    &mut packets,
+                    &stream,
+                    &handler.state_handler,
+                    options.lenient,
+                )
+                .map_err(parse_failed)?;
+                let mut packet = match next {
+                    NextPacket::Packet(packet) => packet,
+                    NextPacket::Undecodable(packet) => {
+                        let copy = packet.can_copy() && writer.keeps(packet.packet_type);
+                        if copy {
+                            let tick = shift_tick(packet.tick, offset.get(), tick_base);
+                            writer.write_undecodable(&packet, tick, &mut out_stream);
+                        }
+                        report.skip(&packet, copy);
+                        if packet.tick >= segment.to {
+                            break;
+                        }
+                        continue;
+                    }
+                    NextPacket::End => break,
+                };
+                let original_tick = packet.tick();
+                if track {
+                    track_packet(&mut state, &mut start_handler, &packet).map_err(parse_failed)?;
+                }
+
+                mutators.mutate_packet(&mut packet, &handler.state_handler);
+
+                if let Some(command) = director
+                    .as_mut()
+                    .and_then(|director| director.command(original_tick))
+                {
+                    let command = Packet::ConsoleCmd(ConsoleCmdPacket {
+                        tick: packet.tick(),
+                        command,
+                    });
+                    writer.write(&command, &mut out_stream, &handler.state_handler);
+                }
+
+                writer.encode(&packet, &mut out_stream, &handler.state_handler);
+                handler.handle_packet(packet).map_err(parse_failed)?;
+
+                if original_tick >= segment.to {
+                    break;
+                }
             }
+            previous_end = segment.to;
         }
+
         PacketType::Stop.write(&mut out_stream).unwrap();
         StopPacket {
-            tick: (end_tick - start_tick).into(),
+            tick: shift_tick(previous_end, offset.get(), tick_base),
         }
         .encode(&mut out_stream, &handler.state_handler)
         .unwrap();
+        writer.patch_header(
+            &mut header,
+            handler.state_handler.demo_meta.interval_per_tick,
+            tick_base.into(),
+        );
+        header
+    };
+    patch_header(&mut out_buffer, &header);
+    report.ticks = header.ticks;
+    report.frames = header.frames;
+    report.duration = header.duration;
+    Ok((out_buffer, report))
+}
+
+/// Move a tick from the source demo to the output, `offset` ticks are removed and the output starts at `base`
+fn shift_tick(tick: DemoTick, offset: DemoTick, base: u32) -> DemoTick {
+    (u32::from(tick) + base)
+        .saturating_sub(offset.into())
+        .into()
+}
+
+/// Where the edited packets are written to
+struct Output<'a, 'b, 'c> {
+    handler: &'b mut DemoHandler<'a, NullHandler>,
+    writer: &'b mut PacketWriter,
+    stream: &'b mut BitWriteStream<'c, LittleEndian>,
+    mutators: &'b MutatorList,
+}
+
+impl Output<'_, '_, '_> {
+    fn write_sync(&mut self, sync: SyncPackets, tick: DemoTick) -> Result<(), ParseError> {
+        for mut packet in sync.tables {
+            self.mutators
+                .mutate_packet(&mut packet, &self.handler.state_handler);
+            packet.set_tick(tick);
+            self.writer
+                .write(&packet, self.stream, &self.handler.state_handler);
+            self.handler.handle_packet(packet)?;
+        }
+        for mut packet in sync.entities {
+            packet.set_tick(tick);
+            self.mutators
+                .observe_packet(&packet, &self.handler.state_handler);
+            self.writer
+                .write(&packet, self.stream, &self.handler.state_handler);
+            self.handler.handle_packet(packet)?;
+        }
+        for mut packet in sync.fill {
+            packet.set_tick(tick);
+            self.writer
+                .write(&packet, self.stream, &self.handler.state_handler);
+        }
+        if let Some(mut packet) = sync.effects {
+            self.mutators
+                .mutate_packet(&mut packet, &self.handler.state_handler);
+            packet.set_tick(tick);
+            self.writer
+                .write(&packet, self.stream, &self.handler.state_handler);
+        }
+        Ok(())
+    }
+}
+
+/// The packets that bring the client to the tracked state
+struct SyncPackets {
+    /// String table changes, these go through the mutators
+    tables: Vec<Packet<'static>>,
+    entities: Vec<Packet<'static>>,
+    /// Net ticks needed for later deltas
+    fill: Vec<Packet<'static>>,
+    /// Sounds and effects that are still active, these go through the mutators
+    effects: Option<Packet<'static>>,
+}
+
+/// Create the packets that sync the output up with the tracked state
+///
+/// `previous_entities` are the entities that exist in the output, any of them that no longer exist are deleted
+fn sync_packets(
+    state: &SkipState,
+    start_state: &ParserState,
+    output_state: &ParserState,
+    packets: RawPacketStream,
+    previous_entities: &BTreeSet<EntityId>,
+) -> SyncPackets {
+    // when starting at the first packet there is no delta yet, the sync needs two ticks before the delta
+    let delta_tick = state.last_delta.max(ServerTick::from(2));
+    let timing = state.net_timing();
+    let net_tick = |tick: ServerTick| net_tick(tick, timing);
+
+    let tables = state
+        .table_updates
+        .clone()
+        .encode(output_state.string_tables.len())
+        .into_iter()
+        .map(|msg| msg_packet(vec![net_tick(delta_tick - 2), msg]))
+        .collect();
+
+    let (baseline_updates, entity_update, mut removed_update) = state.entities.clone().encode(
+        start_state,
+        delta_tick - 2,
+        DemoTick::default(),
+        start_state,
+    );
+    let current_entities = state.entities.entity_ids();
+    let removed_entities = previous_entities
+        .iter()
+        .filter(|entity| !current_entities.contains(entity))
+        .filter(|entity| {
+            !entity_update
+                .entities
+                .iter()
+                .any(|update| update.entity_index == **entity)
+        })
+        .filter(|entity| !removed_update.removed_entities.contains(entity))
+        .copied()
+        .collect::<Vec<_>>();
+    removed_update.removed_entities.extend(removed_entities);
+    removed_update.removed_entities.sort();
+
+    let entities = baseline_updates
+        .into_iter()
+        .map(|msg| msg_packet(vec![net_tick(delta_tick - 2), Message::PacketEntities(msg)]))
+        .chain(once(msg_packet(vec![
+            net_tick(delta_tick - 1),
+            Message::PacketEntities(entity_update),
+        ])))
+        .chain(once(msg_packet(vec![
+            net_tick(delta_tick),
+            Message::PacketEntities(removed_update),
+        ])))
+        .collect();
+
+    let server_tick = state.server_tick;
+    let fill_ticks: Vec<ServerTick> =
+        match referenced_deltas(packets, start_state, delta_tick, server_tick) {
+            Some(mut deltas) => {
+                deltas.insert(server_tick);
+                deltas
+                    .into_iter()
+                    .filter(|tick| *tick > delta_tick)
+                    .collect()
+            }
+            None => (delta_tick + 1).range_inclusive(server_tick).collect(),
+        };
+    let fill = fill_ticks
+        .into_iter()
+        .map(|tick| {
+            msg_packet(vec![
+                net_tick(tick),
+                Message::PacketEntities(PacketEntitiesMessage {
+                    max_entries: state.entity_max,
+                    delta: Some(delta_tick - 1),
+                    ..PacketEntitiesMessage::default()
+                }),
+            ])
+        })
+        .collect();
+
+    // restart the sounds and effects that were active at the start
+    let effects = state.effects.encode(&current_entities, start_state);
+    let effects = (!effects.is_empty()).then(|| msg_packet(effects));
+
+    SyncPackets {
+        tables,
+        entities,
+        fill,
+        effects,
     }
-    out_buffer
 }
 
 struct StartState<'a> {
+    state: SkipState,
+    start_packets: Vec<Packet<'a>>,
+}
+
+/// The state we need to track while skipping to the start tick
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct SkipState {
     entities: ActiveEntities,
     table_updates: StringTablesUpdates,
-    start_packets: Vec<Packet<'a>>,
     server_tick: ServerTick,
     entity_max: u16,
     last_delta: ServerTick,
+    /// `frame_time` and `std_dev` of the last net tick
+    net_timing: Option<(u16, u16)>,
+    effects: ActiveEffects,
+}
+
+impl SkipState {
+    fn net_timing(&self) -> (u16, u16) {
+        // fall back to typical values if the demo has no net ticks before the start
+        self.net_timing.unwrap_or((1881, 263))
+    }
+
+    /// Track the sound precache from the signon, nothing else from the signon needs to be re-created
+    fn handle_preserved(&mut self, packet: &Packet, state: &ParserState) {
+        match packet {
+            Packet::StringTables(packet) => {
+                for (table_id, table) in packet.tables.iter().enumerate() {
+                    self.effects.handle_table(table_id, table);
+                }
+            }
+            Packet::Signon(packet) => {
+                let mut table_count = state.string_tables.len();
+                for msg in &packet.messages {
+                    self.effects.handle_message(msg, table_count, state);
+                    if let Message::CreateStringTable(_) = msg {
+                        table_count += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_packet(&mut self, packet: &Packet, state: &ParserState) {
+        if let Packet::Message(message_packet) = packet {
+            let mut table_count = state.string_tables.len();
+            for msg in &message_packet.messages {
+                self.table_updates.handle_message(msg, table_count);
+                self.effects.handle_message(msg, table_count, state);
+                if let Message::CreateStringTable(_) = msg {
+                    table_count += 1;
+                }
+                match msg {
+                    Message::PacketEntities(msg) => {
+                        if let Some(delta) = msg.delta {
+                            self.last_delta = delta;
+                        }
+                        self.entity_max = msg.max_entries;
+                        self.entities.handle_message(msg, state, packet.tick());
+                    }
+                    Message::NetTick(NetTickMessage {
+                        tick,
+                        frame_time,
+                        std_dev,
+                    }) => {
+                        self.server_tick = *tick;
+                        self.net_timing = Some((*frame_time, *std_dev));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
 }
 
 fn skip_start<'a>(
     handler: &mut DemoHandler<'a, NullHandler>,
     packets: &mut RawPacketStream<'a>,
+    stream: &Stream<'a>,
     start_tick: DemoTick,
-) -> StartState<'a> {
-    let mut entities = ActiveEntities::default();
-    let mut table_updates = StringTablesUpdates::default();
+    index: Option<&SeekIndex>,
+    lenient: bool,
+    report: &mut EditReport,
+) -> Result<StartState<'a>, ParseError> {
+    let mut state = SkipState::default();
     let mut start_packets = Vec::with_capacity(6);
-    let mut server_tick = ServerTick::default();
-    let mut entity_max = 0;
-    let mut last_delta = ServerTick::default();
+    let mut snapshot = index.and_then(|index| index.snapshot_before(start_tick));
 
-    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+    while let Some(packet) = next_decoded(packets, stream, &handler.state_handler, lenient, report)?
+    {
         if PRESERVE_PACKETS.contains(&packet.packet_type()) {
+            state.handle_preserved(&packet, &handler.state_handler);
             start_packets.push(packet.clone());
-            handler.handle_packet(packet).unwrap();
+            handler.handle_packet(packet)?;
         } else if packet.packet_type() != PacketType::ConsoleCmd {
-            if let Packet::Message(message_packet) = &packet {
-                for msg in &message_packet.messages {
-                    table_updates.handle_message(&msg);
-                    match msg {
-                        Message::PacketEntities(msg) => {
-                            if let Some(delta) = msg.delta {
-                                last_delta = delta;
-                            }
-                            entity_max = msg.max_entries;
-                            entities.handle_message(msg, &handler.state_handler, packet.tick());
-                        }
-                        Message::NetTick(NetTickMessage { tick, .. }) => {
-                            server_tick = *tick;
-                        }
-                        _ => {}
+            if let (Some(snapshot), Some(index)) = (snapshot.take(), index) {
+                // once the signon is done we can jump ahead instead of replaying everything,
+                // except for the packets we need to preserve
+                for position in index.late_preserved_before(snapshot.position) {
+                    let mut preserved_stream = stream.clone();
+                    preserved_stream.set_pos(position)?;
+                    let mut preserved_packets = RawPacketStream::new(preserved_stream);
+                    if let Some(preserved) = next_decoded(
+                        &mut preserved_packets,
+                        stream,
+                        &handler.state_handler,
+                        lenient,
+                        report,
+                    )? {
+                        start_packets.push(preserved.clone());
+                        handler.handle_packet(preserved)?;
                     }
                 }
+                report
+                    .skipped
+                    .extend(index.skipped_before(snapshot.tick).cloned());
+                state = snapshot.restore(handler)?;
+                let mut stream = stream.clone();
+                stream.set_pos(snapshot.position)?;
+                *packets = RawPacketStream::new(stream);
+                continue;
             }
+
+            state.handle_packet(&packet, &handler.state_handler);
             let tick = packet.tick();
-            handler.handle_packet(packet).unwrap();
+            handler.handle_packet(packet)?;
 
             if tick >= start_tick {
                 break;
@@ -213,41 +618,96 @@ fn skip_start<'a>(
         }
     }
 
-    StartState {
-        entities,
-        table_updates,
+    Ok(StartState {
+        state,
         start_packets,
-        server_tick,
-        entity_max,
-        last_delta,
+    })
+}
+
+/// Update the tracked state with a packet from the source demo
+fn track_packet<'a>(
+    state: &mut SkipState,
+    handler: &mut DemoHandler<'a, NullHandler>,
+    packet: &Packet<'a>,
+) -> Result<(), ParseError> {
+    if PRESERVE_PACKETS.contains(&packet.packet_type()) {
+        state.handle_preserved(packet, &handler.state_handler);
+    } else if packet.packet_type() != PacketType::ConsoleCmd {
+        state.handle_packet(packet, &handler.state_handler);
+    } else {
+        return Ok(());
+    }
+    handler.handle_packet(packet.clone())
+}
+
+/// Skip over the packets before `tick`, only tracking the state
+fn skip_to<'a>(
+    state: &mut SkipState,
+    handler: &mut DemoHandler<'a, NullHandler>,
+    packets: &mut RawPacketStream<'a>,
+    stream: &Stream<'a>,
+    tick: DemoTick,
+    lenient: bool,
+    report: &mut EditReport,
+) -> Result<(), ParseError> {
+    while let Some(packet) = next_decoded(packets, stream, &handler.state_handler, lenient, report)?
+    {
+        track_packet(state, handler, &packet)?;
+        if packet.packet_type() == PacketType::Message && packet.tick() >= tick {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Read the next packet that can be decoded, packets before the cut that can't be decoded are left out of the state
+fn next_decoded<'a>(
+    packets: &mut RawPacketStream<'a>,
+    stream: &Stream<'a>,
+    state: &ParserState,
+    lenient: bool,
+    report: &mut EditReport,
+) -> Result<Option<Packet<'a>>, ParseError> {
+    loop {
+        match next_packet(packets, stream, state, lenient)? {
+            NextPacket::Packet(packet) => return Ok(Some(packet)),
+            NextPacket::Undecodable(packet) => report.skip(&packet, false),
+            NextPacket::End => return Ok(None),
+        }
     }
 }
 
+/// Remove deletes for entities that don't exist in the output from the packets following a sync
 struct DeleteFilter {
-    current_entities: BTreeSet<EntityId>,
-    till_delta: ServerTick,
+    current_entities: RefCell<BTreeSet<EntityId>>,
+    till_delta: Cell<ServerTick>,
 }
 
 impl DeleteFilter {
     pub fn new(current_entities: BTreeSet<EntityId>, till_delta: ServerTick) -> Self {
         DeleteFilter {
-            current_entities,
-            till_delta,
+            current_entities: RefCell::new(current_entities),
+            till_delta: Cell::new(till_delta),
         }
     }
-}
 
-impl MessageMutator for DeleteFilter {
-    fn mutate_message(&self, message: &mut Message, _state: &ParserState) {
+    /// Start filtering for a new sync
+    pub fn reset(&self, current_entities: BTreeSet<EntityId>, till_delta: ServerTick) {
+        *self.current_entities.borrow_mut() = current_entities;
+        self.till_delta.set(till_delta);
+    }
+
+    fn filter_message(&self, message: &mut Message) {
         if let Message::PacketEntities(message) = message {
             if let Some(delta) = message.delta {
-                if delta < self.till_delta {
+                if delta < self.till_delta.get() {
+                    let current_entities = self.current_entities.borrow();
                     let packet_entities = take(&mut message.entities);
                     message.entities = packet_entities
                         .into_iter()
                         .filter(|ent| match ent.update_type {
                             UpdateType::Delete | UpdateType::Leave => {
-                                self.current_entities.contains(&ent.entity_index)
+                                current_entities.contains(&ent.entity_index)
                             }
                             _ => true,
                         })
@@ -265,10 +725,42 @@ fn msg_packet(messages: Vec<Message>) -> Packet {
     })
 }
 
-fn net_tick(tick: ServerTick) -> Message<'static> {
+fn net_tick(tick: ServerTick, (frame_time, std_dev): (u16, u16)) -> Message<'static> {
     Message::NetTick(NetTickMessage {
         tick,
-        frame_time: 1881,
-        std_dev: 263,
+        frame_time,
+        std_dev,
     })
 }
+
+/// How many packets to look ahead when searching for the deltas used after the cut start
+const DELTA_LOOKAHEAD: usize = 128;
+
+/// Find the ticks in `(from, to]` that the packets following the start use as delta
+///
+/// Returns `None` if we can't tell which ticks will be used
+fn referenced_deltas(
+    mut packets: RawPacketStream,
+    state: &ParserState,
+    from: ServerTick,
+    to: ServerTick,
+) -> Option<BTreeSet<ServerTick>> {
+    let mut deltas = BTreeSet::new();
+    for _ in 0..DELTA_LOOKAHEAD {
+        if let Packet::Message(packet) = packets.next(state).ok()?? {
+            for message in &packet.messages {
+                if let Message::PacketEntities(message) = message {
+                    match message.delta {
+                        // deltas only move forward, later packets won't need any of the filler ticks
+                        Some(delta) if delta > to => return Some(deltas),
+                        Some(delta) if delta > from => {
+                            deltas.insert(delta);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    None
+}
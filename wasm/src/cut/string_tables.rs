@@ -1,37 +1,112 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::BTreeMap;
-use tf_demo_parser::demo::message::stringtable::UpdateStringTableMessage;
+use tf_demo_parser::demo::message::stringtable::{
+    CreateStringTableMessage, UpdateStringTableMessage,
+};
 use tf_demo_parser::demo::message::Message;
-use tf_demo_parser::demo::packet::stringtable::StringTableEntry;
+use tf_demo_parser::demo::packet::stringtable::{self, StringTableEntry};
 
-#[derive(Default)]
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct StringTable {
     entries: BTreeMap<u16, StringTableEntry<'static>>,
 }
 
-#[derive(Default)]
+impl StringTable {
+    fn update(&mut self, index: u16, entry: &StringTableEntry) {
+        let entry = entry.to_owned();
+        match self.entries.get_mut(&index) {
+            // updates without text keep the existing text, but do replace the extra data
+            Some(existing) if entry.text.is_none() => existing.extra_data = entry.extra_data,
+            _ => {
+                self.entries.insert(index, entry);
+            }
+        }
+    }
+}
+
+/// A table created after the signon, which we need to re-create
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CreatedTable {
+    table: stringtable::StringTable<'static>,
+    entries: StringTable,
+}
+
+#[derive(Default, Clone, Serialize, Deserialize)]
 pub struct StringTablesUpdates {
     pub tables: BTreeMap<u8, StringTable>,
+    pub created: BTreeMap<u8, CreatedTable>,
 }
 
 impl StringTablesUpdates {
-    pub fn handle_message(&mut self, message: &Message) {
+    /// `table_count` is the number of tables that exist before the message is handled
+    pub fn handle_message(&mut self, message: &Message, table_count: usize) {
         match message {
+            Message::CreateStringTable(msg) => {
+                let table = &msg.table;
+                let mut entries = StringTable::default();
+                for (id, entry) in &table.entries {
+                    entries.update(*id, entry);
+                }
+                self.created.insert(
+                    table_count as u8,
+                    CreatedTable {
+                        table: stringtable::StringTable {
+                            name: Cow::Owned(table.name.to_string()),
+                            entries: Vec::new(),
+                            max_entries: table.max_entries,
+                            fixed_user_data_size: table.fixed_user_data_size,
+                            client_entries: table.client_entries.as_ref().map(|entries| {
+                                entries.iter().map(StringTableEntry::to_owned).collect()
+                            }),
+                            compressed: false,
+                        },
+                        entries,
+                    },
+                );
+            }
             Message::UpdateStringTable(msg) => {
-                let table = self.tables.entry(msg.table_id).or_default();
+                let table = match self.created.get_mut(&msg.table_id) {
+                    Some(created) => &mut created.entries,
+                    None => self.tables.entry(msg.table_id).or_default(),
+                };
                 for (id, entry) in &msg.entries {
-                    table.entries.insert(*id, entry.to_owned());
+                    table.update(*id, entry);
                 }
             }
             _ => {}
         }
     }
 
-    pub fn encode(self) -> impl IntoIterator<Item = UpdateStringTableMessage<'static>> {
-        self.tables
+    /// Messages to re-create the tables created after the signon, followed by the updates for the other tables
+    ///
+    /// Created tables with an id below `existing_tables` already exist in the output and only get their entries updated
+    pub fn encode(self, existing_tables: usize) -> impl IntoIterator<Item = Message<'static>> {
+        let (existing, created): (Vec<_>, Vec<_>) = self
+            .created
             .into_iter()
-            .map(|(table_id, table)| UpdateStringTableMessage {
-                entries: table.entries.into_iter().collect(),
-                table_id,
+            .partition(|(table_id, _)| (*table_id as usize) < existing_tables);
+        let created = created.into_iter().map(|(_, created)| {
+            Message::CreateStringTable(CreateStringTableMessage {
+                table: stringtable::StringTable {
+                    entries: created.entries.entries.into_iter().collect(),
+                    ..created.table
+                },
             })
+        });
+        let existing = existing
+            .into_iter()
+            .map(|(table_id, created)| (table_id, created.entries));
+        let updates = self
+            .tables
+            .into_iter()
+            .chain(existing)
+            .map(|(table_id, table)| {
+                Message::UpdateStringTable(UpdateStringTableMessage {
+                    entries: table.entries.into_iter().collect(),
+                    table_id,
+                })
+            });
+        created.chain(updates)
     }
 }
@@ -0,0 +1,261 @@
+use crate::header::interval_per_tick;
+use crate::users::user_from_entry;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tf_demo_parser::demo::data::{DemoTick, UserInfo};
+use tf_demo_parser::demo::gamevent::GameEvent;
+use tf_demo_parser::demo::message::packetentities::EntityId;
+use tf_demo_parser::demo::message::{Message, MessageType};
+use tf_demo_parser::demo::packet::stringtable::StringTableEntry;
+use tf_demo_parser::demo::parser::analyser::UserId;
+use tf_demo_parser::demo::parser::MessageHandler;
+use tf_demo_parser::{Demo, DemoParser, ParseError, ParserState};
+
+/// How far ahead of an event we switch to the player involved, in seconds
+const LEAD_TIME: f32 = 2.0;
+
+const KILL_WEIGHT: f32 = 3.0;
+const DEATH_WEIGHT: f32 = 1.0;
+const UBER_WEIGHT: f32 = 4.0;
+const UBER_TARGET_WEIGHT: f32 = 2.0;
+const CAPTURE_WEIGHT: f32 = 2.0;
+const DAMAGE_WEIGHT: f32 = 0.01;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AutoDirectorOptions {
+    /// Minimum time in seconds to stay on a target before switching
+    #[serde(default = "default_min_dwell")]
+    pub min_dwell: f32,
+    #[serde(default)]
+    pub priorities: Vec<PlayerPriority>,
+}
+
+impl Default for AutoDirectorOptions {
+    fn default() -> Self {
+        AutoDirectorOptions {
+            min_dwell: default_min_dwell(),
+            priorities: Vec::new(),
+        }
+    }
+}
+
+fn default_min_dwell() -> f32 {
+    5.0
+}
+
+/// Multiplier for the interest in a player, a priority of 0 will never select the player
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PlayerPriority {
+    pub steam_id: String,
+    pub priority: f32,
+}
+
+struct Highlight {
+    tick: DemoTick,
+    user: UserId,
+    weight: f32,
+}
+
+#[derive(Default)]
+struct HighlightAnalyser {
+    users: BTreeMap<UserId, UserInfo>,
+    highlights: Vec<Highlight>,
+}
+
+impl HighlightAnalyser {
+    fn push(&mut self, tick: DemoTick, user: impl Into<UserId>, weight: f32) {
+        let user = user.into();
+        if self.users.contains_key(&user) {
+            self.highlights.push(Highlight { tick, user, weight });
+        }
+    }
+
+    fn user_for_entity(&self, entity: EntityId) -> Option<UserId> {
+        self.users
+            .values()
+            .find(|user| user.entity_id == entity)
+            .map(|user| user.player_info.user_id)
+    }
+
+    fn handle_event(&mut self, event: &GameEvent, tick: DemoTick) {
+        match event {
+            GameEvent::PlayerDeath(event) => {
+                if event.attacker != event.user_id {
+                    self.push(tick, event.attacker, KILL_WEIGHT);
+                }
+                self.push(tick, event.user_id, DEATH_WEIGHT);
+            }
+            GameEvent::PlayerHurt(event) if event.attacker != event.user_id => {
+                self.push(
+                    tick,
+                    event.attacker,
+                    event.damage_amount as f32 * DAMAGE_WEIGHT,
+                );
+            }
+            GameEvent::PlayerChargeDeployed(event) => {
+                self.push(tick, event.user_id, UBER_WEIGHT);
+                self.push(tick, event.target_id, UBER_TARGET_WEIGHT);
+            }
+            GameEvent::TeamPlayPointCaptured(event) => {
+                // every byte in `cappers` is the entity index of one of the cappers
+                let cappers: Vec<_> = event
+                    .cappers
+                    .as_bytes()
+                    .iter()
+                    .filter_map(|entity| self.user_for_entity(EntityId::from(*entity as u32)))
+                    .collect();
+                for capper in cappers {
+                    self.push(tick, capper, CAPTURE_WEIGHT);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MessageHandler for HighlightAnalyser {
+    type Output = Self;
+
+    fn does_handle(message_type: MessageType) -> bool {
+        message_type == MessageType::GameEvent
+    }
+
+    fn handle_message(&mut self, message: &Message, tick: DemoTick, _parser_state: &ParserState) {
+        if let Message::GameEvent(message) = message {
+            self.handle_event(&message.event, tick);
+        }
+    }
+
+    fn handle_string_entry(
+        &mut self,
+        table: &str,
+        index: usize,
+        entry: &StringTableEntry,
+        _parser_state: &ParserState,
+    ) {
+        if let Some(user) = user_from_entry(table, index, entry) {
+            self.users.insert(user.player_info.user_id, user);
+        }
+    }
+
+    fn into_output(self, _state: &ParserState) -> Self::Output {
+        self
+    }
+}
+
+/// Picks observer targets for STV demos based on the action in the demo
+///
+/// The targets are switched by `spec_player` console commands injected into the output demo
+pub struct Director {
+    schedule: Vec<(DemoTick, UserId)>,
+    next: usize,
+    started: bool,
+}
+
+impl Director {
+    pub fn new(demo: &Demo, options: &AutoDirectorOptions) -> Result<Self, ParseError> {
+        let parser = DemoParser::new_with_analyser(demo.get_stream(), HighlightAnalyser::default());
+        let (header, analyser) = parser.parse()?;
+
+        let priorities: BTreeMap<UserId, f32> = analyser
+            .users
+            .values()
+            .map(|user| {
+                let priority = options
+                    .priorities
+                    .iter()
+                    .find(|priority| priority.steam_id == user.player_info.steam_id)
+                    .map(|priority| priority.priority)
+                    .unwrap_or(1.0);
+                (user.player_info.user_id, priority)
+            })
+            .collect();
+
+        let interval = interval_per_tick(&header);
+        let dwell = (options.min_dwell / interval) as u32;
+        let lead = (LEAD_TIME / interval) as u32;
+
+        let mut highlights = analyser.highlights;
+        highlights.sort_by_key(|highlight| highlight.tick);
+
+        let mut schedule = Vec::new();
+        let mut current: Option<(DemoTick, UserId)> = None;
+        for highlight in highlights.iter() {
+            let mut switch_tick = DemoTick::from(u32::from(highlight.tick).saturating_sub(lead));
+            if let Some((last_switch, target)) = current {
+                if target == highlight.user {
+                    continue;
+                }
+                switch_tick = switch_tick.max(last_switch + dwell);
+            }
+
+            let target = best_target(
+                &highlights,
+                switch_tick,
+                switch_tick + dwell + lead,
+                &priorities,
+            );
+            if let Some(target) = target {
+                if current.map(|(_, current)| current) != Some(target) {
+                    schedule.push((switch_tick, target));
+                    current = Some((switch_tick, target));
+                }
+            }
+        }
+
+        Ok(Director {
+            schedule,
+            next: 0,
+            started: false,
+        })
+    }
+
+    /// Get the console command to run before the packet at `tick`, if any
+    ///
+    /// When multiple switches are due at once, only the last one is returned
+    pub fn command(&mut self, tick: DemoTick) -> Option<String> {
+        let mut target = None;
+        while let Some((switch_tick, user)) = self.schedule.get(self.next) {
+            if *switch_tick > tick {
+                break;
+            }
+            target = Some(*user);
+            self.next += 1;
+        }
+        // target by user id, names can contain characters that break the command
+        let target = u16::from(target?);
+
+        let command = if self.started {
+            format!("spec_player \"#{}\"", target)
+        } else {
+            self.started = true;
+            format!(
+                "spec_autodirector 0; spec_mode 4; spec_player \"#{}\"",
+                target
+            )
+        };
+        Some(command)
+    }
+}
+
+fn best_target(
+    highlights: &[Highlight],
+    from: DemoTick,
+    to: DemoTick,
+    priorities: &BTreeMap<UserId, f32>,
+) -> Option<UserId> {
+    let start = highlights.partition_point(|highlight| highlight.tick < from);
+    let mut interest: BTreeMap<UserId, f32> = BTreeMap::new();
+    for highlight in highlights[start..]
+        .iter()
+        .take_while(|highlight| highlight.tick <= to)
+    {
+        let priority = priorities.get(&highlight.user).copied().unwrap_or(1.0);
+        *interest.entry(highlight.user).or_default() += highlight.weight * priority;
+    }
+    interest
+        .into_iter()
+        .filter(|(_, interest)| *interest > 0.0)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(user, _)| user)
+}
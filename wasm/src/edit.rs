@@ -1,6 +1,12 @@
 use clap::Parser;
-use edit::{edit, EditOptions, TickRange};
+use edit::{
+    bookmark_ranges, cut_highlights, cut_povs, edit_with_report, highlight_reel, parse_bookmarks,
+    repair, Anchor, AnonymizeOptions, AutoDirectorOptions, ChatFilterOptions, EditOptions,
+    EventRange, HighlightOptions, IdleOptions, MetadataOptions, PacketKind, SeekIndex,
+    ServerTickRange, SoundOptions, TickNumbering, TickRange, VoiceOptions,
+};
 use std::fs;
+use std::process;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -13,30 +19,319 @@ struct Args {
     from: Option<u32>,
     #[arg(long)]
     to: Option<u32>,
+    /// Interpret --from and --to as server ticks, which line up between demos of the same match
+    #[arg(long)]
+    server_ticks: bool,
+    /// Other demos of the same match to cut the same server ticks from, writes pov_1.dem, pov_2.dem, ...
+    /// with pov_1.dem cut from the main demo
+    #[arg(long, requires = "server_ticks")]
+    pov: Vec<String>,
+    /// Keep the tick numbers from the source demo when cutting
+    #[arg(long)]
+    keep_ticks: bool,
+    /// Start the cut at this tick number instead of 0
+    #[arg(long)]
+    tick_base: Option<u32>,
+    /// Cut around an event instead, e.g. '{"type": "round_start", "round": 2}'
+    #[arg(long, value_parser = parse_anchor)]
+    at: Option<Anchor>,
+    /// End the event cut at this event instead of the one given by --at
+    #[arg(long, value_parser = parse_anchor)]
+    until: Option<Anchor>,
+    /// Seconds to include before the event
+    #[arg(long, default_value_t = 0.0)]
+    pre_roll: f32,
+    /// Seconds to include after the event
+    #[arg(long, default_value_t = 0.0)]
+    post_roll: f32,
+    /// Automatically switch the spectator target to the action
+    #[arg(long)]
+    auto_director: bool,
+    /// Replace player names and steam ids with pseudonyms
+    #[arg(long)]
+    anonymize: bool,
+    /// Seed for the pseudonyms, use the same seed to get the same pseudonyms across demos
+    #[arg(long)]
+    seed: Option<String>,
+    /// Remove all chat messages
+    #[arg(long)]
+    remove_chat: bool,
+    /// Remove all voice data
+    #[arg(long)]
+    remove_voice: bool,
+    /// Remove sounds matching the pattern, e.g. "music/*"
+    #[arg(long)]
+    remove_sounds: Vec<String>,
+    /// Cut out pauses, warmup and humiliation
+    #[arg(long)]
+    remove_idle: bool,
+    /// Keep the UserCmd packets from the source demo
+    #[arg(long)]
+    keep_user_cmd: bool,
+    /// Template for the server name, "{original}" and "{range}" are replaced by the original name and cut range
+    #[arg(long)]
+    server_name: Option<String>,
+    /// Embed the source demo details and edit options in the output
+    #[arg(long)]
+    provenance: bool,
+    /// Cut the highlights of the player with this steam id into highlight_1.dem, highlight_2.dem, ...
+    #[arg(long)]
+    highlights: Option<String>,
+    /// Minimum number of kills in a streak for --highlights
+    #[arg(long, default_value_t = 3)]
+    min_kills: u32,
+    /// Include hits on players in the air in --highlights
+    #[arg(long)]
+    airborne_hits: bool,
+    /// Include kills on medics with a full charge in --highlights
+    #[arg(long)]
+    medic_drops: bool,
+    /// Cut all --highlights into a single reel.dem instead
+    #[arg(long)]
+    reel: bool,
+    /// Cut every bookmark for this demo from a ds_mark `_events.txt` or P-REC `KillStreaks.txt` file
+    /// into bookmark_1.dem, bookmark_2.dem, ...
+    #[arg(long)]
+    bookmarks: Option<String>,
+    /// Time to include before and after each bookmark, e.g. "5s"
+    #[arg(long, default_value = "5s", value_parser = parse_seconds)]
+    pad: f32,
+    /// Skip packets that can't be decoded instead of aborting
+    #[arg(long)]
+    lenient: bool,
+    /// Repair a truncated demo from a crashed game or server before editing it
+    #[arg(long)]
+    repair: bool,
 }
 
 impl Args {
     fn get_options(&self) -> EditOptions {
         EditOptions {
             unlock_pov: self.unlock_pov,
-            cut: if let (Some(from), Some(to)) = (self.from, self.to) {
-                Some(TickRange {
+            cut: match (self.from, self.to) {
+                (Some(from), Some(to)) if !self.server_ticks => Some(TickRange {
                     from: from.into(),
                     to: to.into(),
-                })
+                }),
+                _ => None,
+            },
+            cut_server_ticks: match (self.from, self.to) {
+                (Some(from), Some(to)) if self.server_ticks => Some(ServerTickRange {
+                    from: from.into(),
+                    to: to.into(),
+                }),
+                _ => None,
+            },
+            tick_numbering: match (self.keep_ticks, self.tick_base) {
+                (true, _) => TickNumbering::Original,
+                (false, Some(base)) => TickNumbering::Base(base.into()),
+                (false, None) => TickNumbering::Zero,
+            },
+            cut_event: self.at.clone().map(|anchor| EventRange {
+                anchor,
+                until: self.until.clone(),
+                pre_roll: self.pre_roll,
+                post_roll: self.post_roll,
+            }),
+            auto_director: self.auto_director.then(AutoDirectorOptions::default),
+            anonymize: self.anonymize.then(|| AnonymizeOptions {
+                seed: self.seed.clone().unwrap_or_default(),
+            }),
+            filter_chat: self.remove_chat.then(ChatFilterOptions::default),
+            remove_voice: self.remove_voice.then(VoiceOptions::default),
+            remove_sounds: (!self.remove_sounds.is_empty()).then(|| SoundOptions {
+                patterns: self.remove_sounds.clone(),
+            }),
+            remove_idle: self.remove_idle.then(IdleOptions::default),
+            keep_packet_types: if self.keep_user_cmd {
+                vec![PacketKind::UserCmd]
             } else {
-                None
+                Vec::new()
             },
+            metadata: self.server_name.clone().map(|server_name| MetadataOptions {
+                server_name: Some(server_name),
+                ..MetadataOptions::default()
+            }),
+            embed_provenance: self.provenance,
+            lenient: self.lenient,
             ..EditOptions::default()
         }
     }
 }
 
+fn parse_seconds(duration: &str) -> Result<f32, String> {
+    let duration = duration.trim();
+    duration
+        .strip_suffix('s')
+        .unwrap_or(duration)
+        .parse()
+        .map_err(|_| {
+            format!(
+                "invalid duration \"{}\", expected something like \"5s\"",
+                duration
+            )
+        })
+}
+
+fn parse_anchor(anchor: &str) -> Result<Anchor, String> {
+    serde_json::from_str(anchor).map_err(|e| e.to_string())
+}
+
 fn main() {
     env_logger::init();
     let args: Args = Args::parse();
     let options = args.get_options();
-    let file = fs::read(&args.path).unwrap();
-    let output = edit(&file, options);
+    let mut file = fs::read(&args.path).unwrap();
+
+    if args.repair {
+        let (repaired, report) = repair(&file).unwrap();
+        eprintln!(
+            "salvaged {} packets ({} ticks), dropped {} bytes{}",
+            report.packets,
+            report.ticks,
+            report.dropped_bytes,
+            if report.added_stop {
+                ", added missing stop packet"
+            } else {
+                ""
+            }
+        );
+        if let Some(error) = report.error {
+            eprintln!("stopped reading at: {}", error);
+        }
+        file = repaired;
+    }
+
+    if let (false, Some(range)) = (args.pov.is_empty(), options.cut_server_ticks) {
+        let mut inputs = vec![file];
+        inputs.extend(args.pov.iter().map(|path| fs::read(path).unwrap()));
+        let inputs: Vec<&[u8]> = inputs.iter().map(Vec::as_slice).collect();
+        for (i, result) in cut_povs(&inputs, range, &options).into_iter().enumerate() {
+            let (output, report) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("pov {}: {}", i + 1, e);
+                    continue;
+                }
+            };
+            for adjustment in report.adjustments {
+                eprintln!("{}", adjustment);
+            }
+            fs::write(format!("pov_{}.dem", i + 1), output).unwrap();
+        }
+        return;
+    }
+
+    if let Some(steam_id) = args.highlights.clone() {
+        let highlight_options = HighlightOptions {
+            min_kills: args.min_kills,
+            airborne_hits: args.airborne_hits,
+            medic_drops: args.medic_drops,
+            ..HighlightOptions::new(steam_id)
+        };
+        if args.reel {
+            match highlight_reel(&file, &highlight_options, &options) {
+                Ok(Some(reel)) => {
+                    eprintln!("reel.dem: {} highlights", reel.highlights.len());
+                    fs::write("reel.dem", reel.output).unwrap();
+                }
+                Ok(None) => eprintln!("no highlights found"),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(1);
+                }
+            }
+            return;
+        }
+        let highlights = cut_highlights(&file, &highlight_options, &options).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        for (i, (highlight, output)) in highlights.into_iter().enumerate() {
+            let path = format!("highlight_{}.dem", i + 1);
+            eprintln!(
+                "{}: ticks {}-{}, {} kills",
+                path, highlight.range.from, highlight.range.to, highlight.kills
+            );
+            fs::write(path, output).unwrap();
+        }
+        return;
+    }
+
+    if let Some(bookmarks_path) = &args.bookmarks {
+        let bookmarks = parse_bookmarks(&fs::read_to_string(bookmarks_path).unwrap());
+        for line in &bookmarks.unparsed {
+            eprintln!("couldn't parse bookmark: {}", line);
+        }
+        let ranges = bookmark_ranges(&file, &args.path, &bookmarks.bookmarks, args.pad)
+            .unwrap_or_else(|e| {
+                eprintln!("failed to parse demo: {}", e);
+                process::exit(1);
+            });
+        if ranges.is_empty() {
+            eprintln!("no bookmarks found for {}", args.path);
+            return;
+        }
+        let index = load_index(&args.path, &file);
+        for (i, range) in ranges.into_iter().enumerate() {
+            let options = EditOptions {
+                cut: Some(range),
+                ..options.clone()
+            };
+            let (output, report) = match edit_with_report(&file, options, Some(&index)) {
+                Ok(result) => result,
+                Err(e) => {
+                    eprintln!("bookmark {}: {}", i + 1, e);
+                    continue;
+                }
+            };
+            for adjustment in report.adjustments {
+                eprintln!("{}", adjustment);
+            }
+            let path = format!("bookmark_{}.dem", i + 1);
+            eprintln!("{}: ticks {}-{}", path, range.from, range.to);
+            fs::write(path, output).unwrap();
+        }
+        return;
+    }
+
+    let index = options.cuts().then(|| load_index(&args.path, &file));
+    let (output, report) = edit_with_report(&file, options, index.as_ref()).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    for adjustment in report.adjustments {
+        eprintln!("{}", adjustment);
+    }
+    for skipped in report.skipped {
+        eprintln!(
+            "{} undecodable packet at tick {}: {}",
+            if skipped.copied { "copied" } else { "dropped" },
+            skipped.tick,
+            skipped.error
+        );
+    }
+    for error in report.integrity_errors {
+        eprintln!("warning: {}", error);
+    }
     fs::write("out.dem", output).unwrap();
 }
+
+/// Load the seek index from the sidecar file next to the demo, building it if needed
+fn load_index(path: &str, file: &[u8]) -> SeekIndex {
+    let index_path = format!("{}.index.json", path);
+    let existing = fs::read(&index_path)
+        .ok()
+        .and_then(|data| serde_json::from_slice::<SeekIndex>(&data).ok())
+        .filter(|index| index.matches(file));
+    existing.unwrap_or_else(|| {
+        let index = SeekIndex::new(file).unwrap_or_else(|e| {
+            eprintln!("failed to parse demo: {}", e);
+            process::exit(1);
+        });
+        if let Err(e) = fs::write(&index_path, serde_json::to_vec(&index).unwrap()) {
+            eprintln!("failed to save seek index to {}: {}", index_path, e);
+        }
+        index
+    })
+}
@@ -0,0 +1,10 @@
+use tf_demo_parser::demo::header::Header;
+
+/// The time between ticks from the duration and tick count in the header, defaults to 66 ticks per second
+pub(crate) fn interval_per_tick(header: &Header) -> f32 {
+    if header.ticks > 0 && header.duration > 0.0 {
+        header.duration / header.ticks as f32
+    } else {
+        0.015
+    }
+}
@@ -0,0 +1,327 @@
+use crate::header::interval_per_tick;
+use crate::users::user_from_entry;
+use crate::{edit_with_index, parse_failed, EditOptions, SeekIndex, TickRange};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tf_demo_parser::demo::data::{DemoTick, UserInfo};
+use tf_demo_parser::demo::gamevent::GameEvent;
+use tf_demo_parser::demo::message::packetentities::{EntityId, UpdateType};
+use tf_demo_parser::demo::message::{Message, MessageType};
+use tf_demo_parser::demo::packet::stringtable::StringTableEntry;
+use tf_demo_parser::demo::parser::analyser::UserId;
+use tf_demo_parser::demo::parser::MessageHandler;
+use tf_demo_parser::demo::sendprop::{SendPropIdentifier, SendPropValue};
+use tf_demo_parser::{Demo, DemoParser, ParseError, ParserState};
+
+const FL_ONGROUND: i64 = 1;
+
+/// Select the highlights of a player
+///
+/// Kills less than `streak_window` seconds apart are grouped into a streak, streaks with at least `min_kills` kills
+/// are highlights. With `airborne_hits` or `medic_drops` set, any group containing a hit on a player in the air or a kill
+/// on a medic with a full charge is also a highlight. Hits and medic drops only count towards a group when they're enabled.
+///
+/// These aren't called airshots since the game doesn't flag airshots, the `custom` field of `player_hurt` has no value
+/// for them. Instead every hit on a player that isn't standing on the ground counts.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct HighlightOptions {
+    pub steam_id: String,
+    #[serde(default = "default_streak_window")]
+    pub streak_window: f32,
+    #[serde(default = "default_min_kills")]
+    pub min_kills: u32,
+    /// Count hits on players in the air
+    ///
+    /// Unlike airshots this includes hitscan and melee hits, and hits on players that just left the ground
+    #[serde(default)]
+    pub airborne_hits: bool,
+    #[serde(default)]
+    pub medic_drops: bool,
+    /// Seconds to include before the first event of a highlight
+    #[serde(default = "default_pre_roll")]
+    pub pre_roll: f32,
+    /// Seconds to include after the last event of a highlight
+    #[serde(default = "default_post_roll")]
+    pub post_roll: f32,
+}
+
+impl HighlightOptions {
+    pub fn new(steam_id: String) -> Self {
+        HighlightOptions {
+            steam_id,
+            streak_window: default_streak_window(),
+            min_kills: default_min_kills(),
+            airborne_hits: false,
+            medic_drops: false,
+            pre_roll: default_pre_roll(),
+            post_roll: default_post_roll(),
+        }
+    }
+}
+
+fn default_streak_window() -> f32 {
+    10.0
+}
+
+fn default_min_kills() -> u32 {
+    3
+}
+
+fn default_pre_roll() -> f32 {
+    5.0
+}
+
+fn default_post_roll() -> f32 {
+    3.0
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct Highlight {
+    pub range: TickRange,
+    pub kills: u32,
+    pub airborne_hits: u32,
+    pub medic_drops: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Moment {
+    Kill,
+    AirborneHit,
+    MedicDrop,
+}
+
+#[derive(Default)]
+struct MomentAnalyser {
+    users: BTreeMap<UserId, UserInfo>,
+    airborne: BTreeSet<EntityId>,
+    moments: Vec<(DemoTick, UserId, Moment)>,
+}
+
+impl MomentAnalyser {
+    fn is_airborne(&self, user: UserId) -> bool {
+        self.users
+            .get(&user)
+            .map(|user| self.airborne.contains(&user.entity_id))
+            .unwrap_or_default()
+    }
+
+    fn handle_event(&mut self, event: &GameEvent, tick: DemoTick) {
+        match event {
+            GameEvent::PlayerDeath(event) if event.attacker != event.user_id => {
+                self.moments
+                    .push((tick, event.attacker.into(), Moment::Kill));
+            }
+            GameEvent::PlayerHurt(event)
+                if event.attacker != event.user_id && self.is_airborne(event.user_id.into()) =>
+            {
+                self.moments
+                    .push((tick, event.attacker.into(), Moment::AirborneHit));
+            }
+            GameEvent::MedicDeath(event) if event.charged && event.attacker != event.user_id => {
+                self.moments
+                    .push((tick, event.attacker.into(), Moment::MedicDrop));
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MessageHandler for MomentAnalyser {
+    type Output = Self;
+
+    fn does_handle(message_type: MessageType) -> bool {
+        matches!(
+            message_type,
+            MessageType::GameEvent | MessageType::PacketEntities
+        )
+    }
+
+    fn handle_message(&mut self, message: &Message, tick: DemoTick, _parser_state: &ParserState) {
+        match message {
+            Message::GameEvent(message) => self.handle_event(&message.event, tick),
+            Message::PacketEntities(message) => {
+                let flags = SendPropIdentifier::new("DT_BasePlayer", "m_fFlags");
+                for entity in &message.entities {
+                    if matches!(entity.update_type, UpdateType::Leave | UpdateType::Delete) {
+                        self.airborne.remove(&entity.entity_index);
+                    }
+                    for prop in entity.props.iter().filter(|prop| prop.identifier == flags) {
+                        if let SendPropValue::Integer(value) = prop.value {
+                            if value & FL_ONGROUND == 0 {
+                                self.airborne.insert(entity.entity_index);
+                            } else {
+                                self.airborne.remove(&entity.entity_index);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_string_entry(
+        &mut self,
+        table: &str,
+        index: usize,
+        entry: &StringTableEntry,
+        _parser_state: &ParserState,
+    ) {
+        if let Some(user) = user_from_entry(table, index, entry) {
+            self.users.insert(user.player_info.user_id, user);
+        }
+    }
+
+    fn into_output(self, _state: &ParserState) -> Self::Output {
+        self
+    }
+}
+
+/// Find the highlights of a player, ordered by tick
+pub fn find_highlights(
+    input: &[u8],
+    options: &HighlightOptions,
+) -> Result<Vec<Highlight>, ParseError> {
+    let demo = Demo::new(input);
+    let parser = DemoParser::new_with_analyser(demo.get_stream(), MomentAnalyser::default());
+    let (header, analyser) = parser.parse()?;
+
+    let interval = interval_per_tick(&header);
+    let window = (options.streak_window.max(0.0) / interval) as u32;
+    let pre_roll = (options.pre_roll.max(0.0) / interval) as u32;
+    let post_roll = (options.post_roll.max(0.0) / interval) as u32;
+
+    let mut moments: Vec<(DemoTick, Moment)> = analyser
+        .moments
+        .iter()
+        .filter(|(_, _, moment)| match moment {
+            Moment::Kill => true,
+            Moment::AirborneHit => options.airborne_hits,
+            Moment::MedicDrop => options.medic_drops,
+        })
+        .filter(|(_, user, _)| {
+            analyser
+                .users
+                .get(user)
+                .map(|user| user.player_info.steam_id == options.steam_id)
+                .unwrap_or_default()
+        })
+        .map(|(tick, _, moment)| (*tick, *moment))
+        .collect();
+    moments.sort_by_key(|(tick, _)| *tick);
+
+    let mut groups: Vec<(DemoTick, DemoTick, Vec<Moment>)> = Vec::new();
+    for (tick, moment) in moments {
+        match groups.last_mut() {
+            Some((_, last, group)) if tick <= *last + window => {
+                *last = tick;
+                group.push(moment);
+            }
+            _ => groups.push((tick, tick, vec![moment])),
+        }
+    }
+
+    let mut highlights: Vec<Highlight> = Vec::new();
+    for (first, last, group) in groups {
+        let count = |kind: Moment| group.iter().filter(|moment| **moment == kind).count() as u32;
+        let highlight = Highlight {
+            range: TickRange {
+                from: DemoTick::from(u32::from(first).saturating_sub(pre_roll)),
+                to: last + post_roll,
+            },
+            kills: count(Moment::Kill),
+            airborne_hits: count(Moment::AirborneHit),
+            medic_drops: count(Moment::MedicDrop),
+        };
+        let selected = highlight.kills >= options.min_kills
+            || (options.airborne_hits && highlight.airborne_hits > 0)
+            || (options.medic_drops && highlight.medic_drops > 0);
+        if !selected {
+            continue;
+        }
+
+        // the padding can make highlights overlap
+        match highlights.last_mut() {
+            Some(previous) if previous.range.to >= highlight.range.from => {
+                previous.range.to = highlight.range.to;
+                previous.kills += highlight.kills;
+                previous.airborne_hits += highlight.airborne_hits;
+                previous.medic_drops += highlight.medic_drops;
+            }
+            _ => highlights.push(highlight),
+        }
+    }
+    Ok(highlights)
+}
+
+/// Cut every highlight of a player into its own demo, applying the other edit options to each cut
+pub fn cut_highlights(
+    input: &[u8],
+    options: &HighlightOptions,
+    edit_options: &EditOptions,
+) -> Result<Vec<(Highlight, Vec<u8>)>, String> {
+    let highlights = find_highlights(input, options).map_err(parse_failed)?;
+    if highlights.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let index = SeekIndex::new(input).map_err(parse_failed)?;
+    highlights
+        .into_iter()
+        .map(|highlight| {
+            let options = EditOptions {
+                cut: Some(highlight.range),
+                cut_event: None,
+                cut_server_ticks: None,
+                ..edit_options.clone()
+            };
+            let output = edit_with_index(input, options, Some(&index))?;
+            Ok((highlight, output))
+        })
+        .collect()
+}
+
+/// All highlights of a player cut into a single demo
+pub struct HighlightReel {
+    pub highlights: Vec<Highlight>,
+    pub output: Vec<u8>,
+}
+
+/// Cut all highlights of a player into a single demo, with the time between the highlights removed
+///
+/// Returns `None` if the player has no highlights.
+pub fn highlight_reel(
+    input: &[u8],
+    options: &HighlightOptions,
+    edit_options: &EditOptions,
+) -> Result<Option<HighlightReel>, String> {
+    let highlights = find_highlights(input, options).map_err(parse_failed)?;
+    let (Some(first), Some(last)) = (highlights.first(), highlights.last()) else {
+        return Ok(None);
+    };
+
+    let gaps = highlights
+        .windows(2)
+        .filter(|pair| pair[1].range.from > pair[0].range.to + 1)
+        .map(|pair| TickRange {
+            from: pair[0].range.to + 1,
+            to: DemoTick::from(u32::from(pair[1].range.from) - 1),
+        });
+    let options = EditOptions {
+        cut: Some(TickRange {
+            from: first.range.from,
+            to: last.range.to,
+        }),
+        cut_event: None,
+        cut_server_ticks: None,
+        remove_ranges: edit_options
+            .remove_ranges
+            .iter()
+            .copied()
+            .chain(gaps)
+            .collect(),
+        ..edit_options.clone()
+    };
+    let output = edit_with_index(input, options, None)?;
+    Ok(Some(HighlightReel { highlights, output }))
+}
@@ -0,0 +1,226 @@
+use crate::header::interval_per_tick;
+use crate::TickRange;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::message::{Message, MessageType};
+use tf_demo_parser::demo::parser::MessageHandler;
+use tf_demo_parser::demo::sendprop::{SendPropIdentifier, SendPropValue};
+use tf_demo_parser::{Demo, DemoParser, ParseError, ParserState};
+
+const ROUND_STATE_PREGAME: i64 = 1;
+const ROUND_STATE_TEAM_WIN: i64 = 5;
+
+/// Select the dead time to remove from the demo
+///
+/// Idle periods shorter than `min_length` seconds are kept.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IdleOptions {
+    /// Remove the time the game is paused
+    #[serde(default = "default_true")]
+    pub pauses: bool,
+    /// Remove the pre-game and waiting for players periods
+    #[serde(default = "default_true")]
+    pub warmup: bool,
+    /// Remove the humiliation period after a round is won
+    #[serde(default = "default_true")]
+    pub humiliation: bool,
+    #[serde(default = "default_min_length")]
+    pub min_length: f32,
+}
+
+impl Default for IdleOptions {
+    fn default() -> Self {
+        IdleOptions {
+            pauses: true,
+            warmup: true,
+            humiliation: true,
+            min_length: default_min_length(),
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_min_length() -> f32 {
+    5.0
+}
+
+#[derive(Default)]
+struct IdleAnalyser {
+    options: IdleOptions,
+    paused: bool,
+    waiting_for_players: bool,
+    round_state: i64,
+    idle_since: Option<DemoTick>,
+    idle: Vec<TickRange>,
+    last_tick: DemoTick,
+}
+
+impl IdleAnalyser {
+    fn new(options: IdleOptions) -> Self {
+        IdleAnalyser {
+            options,
+            ..IdleAnalyser::default()
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        (self.options.pauses && self.paused)
+            || (self.options.warmup
+                && (self.waiting_for_players || self.round_state == ROUND_STATE_PREGAME))
+            || (self.options.humiliation && self.round_state == ROUND_STATE_TEAM_WIN)
+    }
+
+    fn update(&mut self, tick: DemoTick) {
+        match (self.idle_since, self.is_idle()) {
+            (None, true) => self.idle_since = Some(tick),
+            (Some(since), false) => {
+                self.idle.push(TickRange {
+                    from: since,
+                    to: DemoTick::from(u32::from(tick).saturating_sub(1)).max(since),
+                });
+                self.idle_since = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+impl MessageHandler for IdleAnalyser {
+    type Output = Vec<TickRange>;
+
+    fn does_handle(message_type: MessageType) -> bool {
+        matches!(
+            message_type,
+            MessageType::SetPause | MessageType::PacketEntities
+        )
+    }
+
+    fn handle_message(&mut self, message: &Message, tick: DemoTick, _parser_state: &ParserState) {
+        self.last_tick = tick;
+        match message {
+            Message::SetPause(message) => self.paused = message.pause,
+            Message::PacketEntities(message) => {
+                let waiting =
+                    SendPropIdentifier::new("DT_TeamplayRoundBasedRules", "m_bInWaitingForPlayers");
+                let round_state =
+                    SendPropIdentifier::new("DT_TeamplayRoundBasedRules", "m_iRoundState");
+                for prop in message
+                    .entities
+                    .iter()
+                    .flat_map(|entity| entity.props.iter())
+                {
+                    if let SendPropValue::Integer(value) = prop.value {
+                        if prop.identifier == waiting {
+                            self.waiting_for_players = value != 0;
+                        } else if prop.identifier == round_state {
+                            self.round_state = value;
+                        }
+                    }
+                }
+            }
+            _ => return,
+        }
+        self.update(tick);
+    }
+
+    fn into_output(mut self, _state: &ParserState) -> Self::Output {
+        if let Some(since) = self.idle_since {
+            self.idle.push(TickRange {
+                from: since,
+                to: self.last_tick,
+            });
+        }
+        self.idle
+    }
+}
+
+/// Find the idle periods in the demo
+pub fn find_idle(demo: &Demo, options: &IdleOptions) -> Result<Vec<TickRange>, ParseError> {
+    let parser =
+        DemoParser::new_with_analyser(demo.get_stream(), IdleAnalyser::new(options.clone()));
+    let (header, idle) = parser.parse()?;
+    let min_length = (options.min_length.max(0.0) / interval_per_tick(&header)) as u32;
+    Ok(idle
+        .into_iter()
+        .filter(|range| u32::from(range.to) - u32::from(range.from) >= min_length)
+        .collect())
+}
+
+/// Split the range into the parts that aren't idle, returns the kept and removed ranges
+///
+/// The idle ranges have to be sorted by their start, but can overlap.
+pub fn split_idle(range: TickRange, idle: &[TickRange]) -> (Vec<TickRange>, Vec<TickRange>) {
+    let mut kept = Vec::new();
+    let mut removed = Vec::new();
+    let mut from = range.from;
+    for idle in idle {
+        if idle.to < from || idle.from > range.to {
+            continue;
+        }
+        if idle.from > from {
+            kept.push(TickRange {
+                from,
+                to: DemoTick::from(u32::from(idle.from) - 1),
+            });
+        }
+        removed.push(TickRange {
+            from: idle.from.max(from),
+            to: idle.to.min(range.to),
+        });
+        from = idle.to + 1;
+    }
+    if from <= range.to {
+        kept.push(TickRange { from, to: range.to });
+    }
+    (kept, removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(from: u32, to: u32) -> TickRange {
+        TickRange {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn split_around_idle() {
+        let (kept, removed) = split_idle(range(0, 100), &[range(10, 19), range(50, 59)]);
+        assert_eq!(kept, vec![range(0, 9), range(20, 49), range(60, 100)]);
+        assert_eq!(removed, vec![range(10, 19), range(50, 59)]);
+    }
+
+    #[test]
+    fn split_clamps_idle_to_range() {
+        let (kept, removed) = split_idle(range(20, 80), &[range(0, 29), range(70, 200)]);
+        assert_eq!(kept, vec![range(30, 69)]);
+        assert_eq!(removed, vec![range(20, 29), range(70, 80)]);
+    }
+
+    #[test]
+    fn split_overlapping_idle() {
+        let (kept, removed) = split_idle(range(0, 100), &[range(10, 40), range(30, 50)]);
+        assert_eq!(kept, vec![range(0, 9), range(51, 100)]);
+        assert_eq!(removed, vec![range(10, 40), range(41, 50)]);
+    }
+
+    #[test]
+    fn split_without_idle_in_range() {
+        let (kept, removed) = split_idle(range(20, 80), &[range(0, 10), range(90, 100)]);
+        assert_eq!(kept, vec![range(20, 80)]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn split_fully_idle() {
+        let (kept, removed) = split_idle(range(20, 80), &[range(0, 100)]);
+        assert!(kept.is_empty());
+        assert_eq!(removed, vec![range(20, 80)]);
+    }
+}
@@ -1,28 +1,72 @@
+mod anchor;
+mod anonymize;
+mod bookmarks;
+mod builder;
+mod chat;
 mod clean;
 mod cond;
 mod cut;
+mod director;
+mod header;
+mod highlights;
+mod idle;
+mod metadata;
 pub mod missing_preserve;
 mod mutate;
 mod options;
+mod packets;
 mod pov;
+mod provenance;
+mod repair;
+mod report;
+mod server_ticks;
+mod sounds;
+mod users;
+mod voice;
 
 use bitbuffer::{BitRead, BitWriteStream, LittleEndian};
+use serde::Serialize;
 use tf_demo_parser::demo::header::Header;
 use tf_demo_parser::demo::message::packetentities::EntityId;
-use tf_demo_parser::demo::packet::PacketType;
-use tf_demo_parser::demo::parser::{DemoHandler, Encode, RawPacketStream};
-use tf_demo_parser::{Demo, DemoParser};
+use tf_demo_parser::demo::packet::consolecmd::ConsoleCmdPacket;
+use tf_demo_parser::demo::packet::{Packet, PacketType};
+use tf_demo_parser::demo::parser::{DemoHandler, RawPacketStream};
+use tf_demo_parser::{Demo, DemoParser, ParseError};
 use wasm_bindgen::prelude::*;
 
 use bitbuffer::BitWrite;
 use tf_demo_parser::demo::data::DemoTick;
 
+use crate::anchor::resolve_range;
+pub use crate::anchor::{Anchor, EventRange};
+pub use crate::anonymize::{AnonymizeMapping, AnonymizeOptions};
+pub use crate::bookmarks::{bookmark_ranges, parse_bookmarks, Bookmark, BookmarkFile};
+pub use crate::builder::DemoBuilder;
+pub use crate::chat::{ChatChannel, ChatFilterOptions};
 use crate::clean::clean_demo;
 use crate::cond::strip_cond;
 use crate::cut::cut;
+pub use crate::cut::{compare_state, Divergence, SeekIndex, StateComparison, TickDivergence};
+use crate::director::Director;
+pub use crate::director::{AutoDirectorOptions, PlayerPriority};
+pub use crate::highlights::{
+    cut_highlights, find_highlights, highlight_reel, Highlight, HighlightOptions, HighlightReel,
+};
+pub use crate::idle::IdleOptions;
+pub use crate::metadata::MetadataOptions;
 use crate::mutate::{MutatorList, PacketMutator};
-pub use crate::options::{CondOptions, EditOptions, TickRange};
+pub use crate::options::{CondOptions, EditOptions, TickNumbering, TickRange};
+pub use crate::packets::PacketKind;
+use crate::packets::{next_packet, NextPacket, PacketWriter, HEADER_SIZE};
 use crate::pov::unlock_pov;
+pub use crate::provenance::read_provenance;
+use crate::provenance::Provenance;
+pub use crate::repair::{repair, RepairReport};
+pub use crate::report::{EditReport, SkippedPacket};
+use crate::server_ticks::resolve_server_range;
+pub use crate::server_ticks::{cut_povs, ServerTickRange};
+pub use crate::sounds::SoundOptions;
+pub use crate::voice::VoiceOptions;
 
 extern crate web_sys;
 
@@ -38,23 +82,191 @@ fn set_panic_hook() {
 }
 
 #[wasm_bindgen]
-pub fn edit_js(input: &[u8], options: JsValue) -> Vec<u8> {
+pub fn edit_js(input: &[u8], options: JsValue) -> Result<Vec<u8>, JsValue> {
     set_panic_hook();
     let options: EditOptions = serde_wasm_bindgen::from_value(options).expect("invalid options");
-    edit(input, options)
+    edit(input, options).map_err(|e| JsValue::from_str(&e))
 }
 
-pub fn edit(input: &[u8], options: EditOptions) -> Vec<u8> {
-    if options.cut.is_some() {
-        cut(input, options)
+pub fn edit(input: &[u8], options: EditOptions) -> Result<Vec<u8>, String> {
+    edit_with_index(input, options, None)
+}
+
+/// Edit the demo, using the seek index to speed up cutting
+pub fn edit_with_index(
+    input: &[u8],
+    options: EditOptions,
+    index: Option<&SeekIndex>,
+) -> Result<Vec<u8>, String> {
+    edit_with_report(input, options, index).map(|(output, _)| output)
+}
+
+/// Edit the demo, returning details about the output and any adjustments made to the requested edit
+///
+/// Fails if the options would make the demo unplayable or the range to cut can't be found in the demo,
+/// instead of silently editing the whole demo.
+pub fn edit_with_report(
+    input: &[u8],
+    mut options: EditOptions,
+    index: Option<&SeekIndex>,
+) -> Result<(Vec<u8>, EditReport), String> {
+    options.validate()?;
+    let mut adjustments = Vec::new();
+    if let Some(range) = options.cut_server_ticks.take() {
+        let resolved = resolve_server_range(&Demo::new(input), range)?;
+        if resolved.covered != range {
+            adjustments.push(format!(
+                "The demo only covers server ticks {} to {} of {} to {}, cutting those",
+                resolved.covered.from, resolved.covered.to, range.from, range.to
+            ));
+        }
+        options.cut = Some(resolved.ticks);
+    }
+    if let Some(event_range) = options.cut_event.take() {
+        let range = resolve_range(&Demo::new(input), &event_range)?;
+        if range.from > range.to {
+            return Err(format!(
+                "The cut ends at tick {} before it starts at tick {}",
+                range.to, range.from
+            ));
+        }
+        options.cut = Some(range);
+    }
+    let index = index.filter(|index| {
+        let matches = index.matches(input);
+        if !matches {
+            log::warn!("seek index doesn't match the demo, ignoring");
+        }
+        matches
+    });
+    let (output, mut report) = if options.cuts() {
+        cut(input, options, index)?
     } else {
-        no_cut(input, options)
+        no_cut(input, options)?
+    };
+    for adjustment in adjustments {
+        report.adjust(adjustment);
+    }
+    Ok((output, report))
+}
+
+/// Describe an error from parsing the input demo
+pub(crate) fn parse_failed(e: ParseError) -> String {
+    format!("Failed to parse the demo: {}", e)
+}
+
+/// A demo loaded for editing, keeps the seek index around between edits
+#[wasm_bindgen]
+pub struct EditSession {
+    input: Vec<u8>,
+    index: Option<SeekIndex>,
+    report: EditReport,
+}
+
+#[wasm_bindgen]
+impl EditSession {
+    #[wasm_bindgen(constructor)]
+    pub fn new(input: Vec<u8>) -> EditSession {
+        set_panic_hook();
+        EditSession {
+            input,
+            index: None,
+            report: EditReport::default(),
+        }
+    }
+
+    pub fn edit(&mut self, options: JsValue) -> Result<Vec<u8>, JsValue> {
+        let options: EditOptions =
+            serde_wasm_bindgen::from_value(options).expect("invalid options");
+        if options.cuts() && self.index.is_none() {
+            let index =
+                SeekIndex::new(&self.input).map_err(|e| JsValue::from_str(&parse_failed(e)))?;
+            self.index = Some(index);
+        }
+        let (output, report) = edit_with_report(&self.input, options, self.index.as_ref())
+            .map_err(|e| JsValue::from_str(&e))?;
+        self.report = report;
+        Ok(output)
+    }
+
+    /// The report for the last edit
+    pub fn report(&self) -> JsValue {
+        self.report
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .expect("failed to serialize report")
+    }
+}
+
+#[wasm_bindgen]
+pub fn anonymize_mapping_js(input: &[u8], options: JsValue) -> Result<JsValue, JsValue> {
+    set_panic_hook();
+    let options: AnonymizeOptions =
+        serde_wasm_bindgen::from_value(options).expect("invalid options");
+    let mapping =
+        anonymize_mapping(input, &options).map_err(|e| JsValue::from_str(&parse_failed(e)))?;
+    Ok(mapping
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .expect("failed to serialize mapping"))
+}
+
+/// Get the pseudonyms that will be used when anonymizing the demo with the provided options
+pub fn anonymize_mapping(
+    input: &[u8],
+    options: &AnonymizeOptions,
+) -> Result<AnonymizeMapping, ParseError> {
+    AnonymizeMapping::new(&Demo::new(input), options)
+}
+
+#[wasm_bindgen]
+pub fn read_provenance_js(input: &[u8]) -> JsValue {
+    set_panic_hook();
+    read_provenance(input)
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .expect("failed to serialize provenance")
+}
+
+#[wasm_bindgen]
+pub fn find_highlights_js(input: &[u8], options: JsValue) -> Result<JsValue, JsValue> {
+    set_panic_hook();
+    let options: HighlightOptions =
+        serde_wasm_bindgen::from_value(options).expect("invalid options");
+    let highlights =
+        find_highlights(input, &options).map_err(|e| JsValue::from_str(&parse_failed(e)))?;
+    Ok(highlights
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .expect("failed to serialize highlights"))
+}
+
+#[derive(Serialize)]
+struct BookmarkRanges {
+    ranges: Vec<TickRange>,
+    unparsed: Vec<String>,
+}
+
+/// Parse a `ds_mark` or P-REC bookmark file and get the padded tick ranges for the bookmarks in this demo,
+/// along with the lines of the file that couldn't be parsed
+#[wasm_bindgen]
+pub fn bookmark_ranges_js(
+    input: &[u8],
+    demo_path: &str,
+    bookmarks: &str,
+    pad: f32,
+) -> Result<JsValue, JsValue> {
+    set_panic_hook();
+    let file = parse_bookmarks(bookmarks);
+    let ranges = bookmark_ranges(input, demo_path, &file.bookmarks, pad)
+        .map_err(|e| JsValue::from_str(&parse_failed(e)))?;
+    Ok(BookmarkRanges {
+        ranges,
+        unparsed: file.unparsed,
     }
+    .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+    .expect("failed to serialize ranges"))
 }
 
 #[wasm_bindgen]
 pub fn count_ticks(input: &[u8]) -> u32 {
-    let demo = Demo::new(&input);
+    let demo = Demo::new(input);
     let mut stream = demo.get_stream();
     let header = Header::read(&mut stream).unwrap();
 
@@ -64,52 +276,156 @@ pub fn count_ticks(input: &[u8]) -> u32 {
     let mut handler = DemoHandler::default();
     handler.handle_header(&header);
 
-    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+    // stop at the first unreadable packet, so truncated demos still give the ticks they contain
+    while let Ok(Some(packet)) = packets.next(&handler.state_handler) {
         tick = packet.tick();
-        handler.handle_packet(packet).unwrap();
+        if handler.handle_packet(packet).is_err() {
+            break;
+        }
     }
 
     tick.into()
 }
 
-fn no_cut(input: &[u8], options: EditOptions) -> Vec<u8> {
+/// A repaired demo with the details of what could be salvaged
+#[wasm_bindgen]
+pub struct RepairResult {
+    output: Vec<u8>,
+    report: RepairReport,
+}
+
+#[wasm_bindgen]
+impl RepairResult {
+    pub fn output(&self) -> Vec<u8> {
+        self.output.clone()
+    }
+
+    pub fn report(&self) -> JsValue {
+        self.report
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .expect("failed to serialize report")
+    }
+}
+
+#[wasm_bindgen]
+pub fn repair_js(input: &[u8]) -> Result<RepairResult, JsValue> {
+    set_panic_hook();
+    let (output, report) = repair(input).map_err(|e| JsValue::from_str(&e))?;
+    Ok(RepairResult { output, report })
+}
+
+fn no_cut(input: &[u8], options: EditOptions) -> Result<(Vec<u8>, EditReport), String> {
     let mut out_buffer = Vec::with_capacity(input.len());
-    {
+    let mut report = EditReport::default();
+    let header = {
         let mut out_stream = BitWriteStream::new(&mut out_buffer, LittleEndian);
 
-        let demo = Demo::new(&input);
+        let demo = Demo::new(input);
         let spectator_id = find_stv(&demo).unwrap_or_else(|| EntityId::from(1u32));
 
         let mut stream = demo.get_stream();
-        let header = Header::read(&mut stream).unwrap();
+        let mut header = Header::read(&mut stream).unwrap();
+
+        let mutators = options
+            .as_mutator(&demo, spectator_id)
+            .map_err(parse_failed)?;
+        let mut provenance = options
+            .embed_provenance
+            .then(|| Provenance::new(input, &header, &options, DemoTick::default()));
+        mutators.mutate_header(&mut header);
         header.write(&mut out_stream).unwrap();
 
         let mut packets = RawPacketStream::new(stream.clone());
         let mut handler = DemoHandler::default();
         handler.handle_header(&header);
 
-        let mutators = options.as_mutator(spectator_id);
+        let mut writer =
+            PacketWriter::new(&options, &[PacketType::ConsoleCmd, PacketType::UserCmd]);
+
+        let mut director = options
+            .auto_director
+            .as_ref()
+            .map(|director_options| Director::new(&demo, director_options))
+            .transpose()
+            .map_err(parse_failed)?;
 
-        while let Some(mut packet) = packets.next(&handler.state_handler).unwrap() {
+        loop {
+            let next = next_packet(
+                &mut packets,
+                &stream,
+                &handler.state_handler,
+                options.lenient,
+            )
+            .map_err(parse_failed)?;
+            let mut packet = match next {
+                NextPacket::Packet(packet) => packet,
+                NextPacket::Undecodable(packet) => {
+                    let copy = packet.can_copy() && writer.keeps(packet.packet_type);
+                    if copy {
+                        writer.write_undecodable(&packet, packet.tick, &mut out_stream);
+                    }
+                    report.skip(&packet, copy);
+                    continue;
+                }
+                NextPacket::End => break,
+            };
+            let original_tick = packet.tick();
             mutators.mutate_packet(&mut packet, &handler.state_handler);
 
-            if packet.packet_type() != PacketType::ConsoleCmd
-                && packet.packet_type() != PacketType::UserCmd
+            if packet.packet_type() == PacketType::Message {
+                if let Some(provenance) = provenance.take() {
+                    for provenance_packet in provenance.packets(packet.tick()) {
+                        writer.write(&provenance_packet, &mut out_stream, &handler.state_handler);
+                    }
+                }
+            }
+
+            if let Some(command) = director
+                .as_mut()
+                .and_then(|director| director.command(original_tick))
             {
-                packet
-                    .encode(&mut out_stream, &handler.state_handler)
-                    .unwrap();
+                let command = Packet::ConsoleCmd(ConsoleCmdPacket {
+                    tick: packet.tick(),
+                    command,
+                });
+                writer.write(&command, &mut out_stream, &handler.state_handler);
             }
-            handler.handle_packet(packet).unwrap();
+
+            writer.encode(&packet, &mut out_stream, &handler.state_handler);
+            handler.handle_packet(packet).map_err(parse_failed)?;
         }
+        writer.patch_header(
+            &mut header,
+            handler.state_handler.demo_meta.interval_per_tick,
+            DemoTick::default(),
+        );
+        header
+    };
+    patch_header(&mut out_buffer, &header);
+    report.ticks = header.ticks;
+    report.frames = header.frames;
+    report.duration = header.duration;
+    Ok((out_buffer, report))
+}
+
+/// Overwrite the header at the start of the output once the final values are known
+fn patch_header(buffer: &mut [u8], header: &Header) {
+    let mut data = Vec::with_capacity(HEADER_SIZE);
+    {
+        let mut stream = BitWriteStream::new(&mut data, LittleEndian);
+        header.write(&mut stream).unwrap();
     }
-    out_buffer
+    buffer[..data.len()].copy_from_slice(&data);
 }
 
 fn find_stv(demo: &Demo) -> Option<EntityId> {
     let parser = DemoParser::new(demo.get_stream());
-    let (_, data) = parser.parse().expect("failed to parse demo");
-    data.users
+    let (_, mut ticker) = parser.ticker().ok()?;
+    // the stv bot joins right at the start, so a packet that can't be decoded later on doesn't matter
+    while let Ok(true) = ticker.tick() {}
+    ticker
+        .state()
+        .users
         .values()
         .find(|user| user.steam_id == "BOT")
         .map(|user| user.entity_id)
@@ -0,0 +1,143 @@
+use crate::mutate::MutatorList;
+use crate::TickRange;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::Message;
+
+const DEFAULT_SERVER_NAME: &str = "{original} - Edited";
+
+/// Templates for the demo metadata
+///
+/// The demo type in the header can't be changed, the game only plays demos with the `HL2DEMO` type.
+/// `{original}` is replaced by the original value and `{range}` by the cut range (e.g. `1000-2000`),
+/// or an empty string when the demo isn't cut. Fields that aren't set are left as is,
+/// except for the server name which gets " - Edited" appended by default.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MetadataOptions {
+    /// The server field of the header
+    #[serde(default)]
+    pub server: Option<String>,
+    #[serde(default)]
+    pub nick: Option<String>,
+    /// The server name from the server info message
+    #[serde(default)]
+    pub server_name: Option<String>,
+}
+
+/// Fill in a template, skipping any suffix that the original value already has
+fn apply_template(template: &str, original: &str, range: &str) -> String {
+    if let Some(suffix) = template.strip_prefix("{original}") {
+        if !suffix.is_empty() && !suffix.contains('{') && original.ends_with(suffix) {
+            return original.to_string();
+        }
+    }
+    template
+        .replace("{original}", original)
+        .replace("{range}", range)
+}
+
+/// Truncate the value to fit in the fixed size header field, leaving room for the null terminator
+fn fit(mut value: String, size: usize) -> String {
+    if value.len() >= size {
+        let mut end = size - 1;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        value.truncate(end);
+    }
+    value
+}
+
+fn apply_header(header: &mut Header, options: &MetadataOptions, range: &str) {
+    if let Some(template) = &options.server {
+        header.server = fit(apply_template(template, &header.server, range), 260);
+    }
+    if let Some(template) = &options.nick {
+        header.nick = fit(apply_template(template, &header.nick, range), 260);
+    }
+}
+
+/// Rewrite the header and server info according to the templates
+pub fn rewrite_metadata(
+    mutators: &mut MutatorList,
+    options: MetadataOptions,
+    cut: Option<TickRange>,
+) {
+    let range = cut
+        .map(|range| format!("{}-{}", u32::from(range.from), u32::from(range.to)))
+        .unwrap_or_default();
+    let server_name = options
+        .server_name
+        .clone()
+        .unwrap_or_else(|| DEFAULT_SERVER_NAME.to_string());
+
+    let header_range = range.clone();
+    mutators.push_header_mutator(move |header: &mut Header| {
+        apply_header(header, &options, &header_range)
+    });
+    mutators.push_message_mutator(move |message: &mut Message| {
+        if let Message::ServerInfo(info) = message {
+            info.server_name = apply_template(&server_name, &info.server_name, &range);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_replaces_original_and_range() {
+        assert_eq!(
+            apply_template("{original} ({range})", "server", "100-200"),
+            "server (100-200)"
+        );
+        assert_eq!(apply_template("cut {range}", "server", ""), "cut ");
+        assert_eq!(apply_template("fixed", "server", "100-200"), "fixed");
+    }
+
+    #[test]
+    fn template_skips_existing_suffix() {
+        assert_eq!(
+            apply_template("{original} - Edited", "server - Edited", ""),
+            "server - Edited"
+        );
+        // suffixes with placeholders are always applied
+        assert_eq!(
+            apply_template("{original} {range}", "server 1-2", "1-2"),
+            "server 1-2 1-2"
+        );
+    }
+
+    #[test]
+    fn fit_truncates_at_char_boundary() {
+        assert_eq!(fit("short".into(), 260), "short");
+        assert_eq!(fit("abcdef".into(), 4), "abc");
+        assert_eq!(fit("aé".into(), 3), "a");
+    }
+
+    #[test]
+    fn header_keeps_unset_fields() {
+        let mut header = Header {
+            demo_type: "HL2DEMO".into(),
+            version: 3,
+            protocol: 24,
+            server: "server".into(),
+            nick: "nick".into(),
+            map: "map".into(),
+            game: "tf".into(),
+            duration: 1.0,
+            ticks: 66,
+            frames: 66,
+            signon: 0,
+        };
+        let options = MetadataOptions {
+            nick: Some("{original} {range}".into()),
+            ..MetadataOptions::default()
+        };
+        apply_header(&mut header, &options, "1-2");
+        assert_eq!(header.nick, "nick 1-2");
+        assert_eq!(header.server, "server");
+        assert_eq!(header.demo_type, "HL2DEMO");
+    }
+}
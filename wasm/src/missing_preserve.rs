@@ -1,71 +1,253 @@
 use crate::mutate::MessageMutator;
-use log::{info, warn};
+use log::warn;
 use std::cell::RefCell;
 use std::collections::{BTreeMap, BTreeSet};
-use tf_demo_parser::demo::message::packetentities::{EntityId, PacketEntity, UpdateType};
+use std::mem::take;
+use tf_demo_parser::demo::message::packetentities::{
+    EntityId, PacketEntitiesMessage, PacketEntity, UpdateType,
+};
 use tf_demo_parser::demo::message::Message;
-use tf_demo_parser::demo::packet::datatable::{ClassId, ServerClass};
+use tf_demo_parser::demo::packet::datatable::ClassId;
 use tf_demo_parser::ParserState;
 
+/// The highest serial number that fits in the 10 bits it's sent with
+const MAX_SERIAL: u32 = (1 << 10) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum EntityState {
+    InPvs,
+    Dormant,
+    Deleted,
+}
+
+/// What the client knows about an entity
+#[derive(Debug, Clone, Copy)]
+struct EntityLifecycle {
+    class: ClassId,
+    serial: u32,
+    baseline: usize,
+    state: EntityState,
+}
+
+/// Keep the entity updates consistent with the entities the client knows about
+///
+/// Cutting and filtering can leave updates for entities that the client never saw or already deleted,
+/// which show up as "ERROR" entities or crash the client. Preserve updates for entities that were deleted
+/// are rewritten into `Enter` updates from the baseline with the last known serial, entities that changed class
+/// enter with a new serial, preserves for entities that never existed are dropped. Removing an entity still in
+/// the PVS crashes the client later on, so those entities leave the PVS first and are removed with the next update.
+///
+/// Entity updates written without going through the mutators, like the synced state of a cut,
+/// have to be passed to `observe_message` to keep track of them.
 #[derive(Default)]
-pub struct RemoveInvalidPreserveEntity {
-    known_entities: RefCell<BTreeSet<EntityId>>,
-    deferred_delete: RefCell<Vec<EntityId>>,
+pub struct FixEntityConsistency {
+    entities: RefCell<BTreeMap<EntityId, EntityLifecycle>>,
+    /// Entities that were removed while in the PVS, they're removed after leaving the PVS
+    pending_removals: RefCell<BTreeSet<EntityId>>,
 }
 
-impl RemoveInvalidPreserveEntity {
-    pub fn new() -> RemoveInvalidPreserveEntity {
-        RemoveInvalidPreserveEntity::default()
+impl FixEntityConsistency {
+    pub fn new() -> FixEntityConsistency {
+        FixEntityConsistency::default()
     }
-}
 
-impl MessageMutator for RemoveInvalidPreserveEntity {
-    fn mutate_message(&self, message: &mut Message, _state: &ParserState) {
-        if let Message::PacketEntities(ent_message) = message {
-            let deferred_deletes = self.deferred_delete.take();
-            for entity in ent_message.entities.iter() {
-                match entity.update_type {
-                    UpdateType::Enter => {
-                        self.known_entities.borrow_mut().insert(entity.entity_index);
-                    }
-                    UpdateType::Preserve => {
-                        if !self.known_entities.borrow().contains(&entity.entity_index) {
-                            warn!("preserving missing entity {}", entity.entity_index);
-                        }
+    fn fix(&self, message: &mut PacketEntitiesMessage, state: &ParserState) {
+        let mut entities = self.entities.borrow_mut();
+        let mut pending_removals = self.pending_removals.borrow_mut();
+        if message.delta.is_none() {
+            // a full update replaces all entities on the client
+            for entity in entities.values_mut() {
+                entity.state = EntityState::Deleted;
+            }
+            pending_removals.clear();
+        }
+
+        message.entities.sort_by_key(|entity| entity.entity_index);
+        message.entities.dedup_by_key(|entity| entity.entity_index);
+
+        // the entities left the PVS with the previous update, unless they're updated again they can be removed now
+        for id in take(&mut *pending_removals) {
+            let updated = message
+                .entities
+                .iter()
+                .any(|entity| entity.entity_index == id);
+            if !updated {
+                message.removed_entities.push(id);
+            }
+        }
+
+        let base_line = message.base_line as usize;
+        message.entities.retain_mut(|entity| {
+            let previous = entities.get(&entity.entity_index).copied();
+            match (entity.update_type, previous) {
+                (UpdateType::Preserve, Some(known))
+                    if known.state == EntityState::Deleted
+                        || known.class != entity.server_class =>
+                {
+                    warn!(
+                        "preserving missing entity {}, entering it instead",
+                        entity.entity_index
+                    );
+                    if known.class == entity.server_class {
+                        enter(entity, known.serial, known.baseline, state);
+                    } else {
+                        // a different entity took over the slot, the client has to see it as new
+                        enter(entity, known.serial % MAX_SERIAL + 1, base_line, state);
                     }
-                    UpdateType::Delete => {
-                        self.known_entities.borrow_mut().remove(&entity.entity_index);
-                    },
-                    _ => {}
-                };
+                }
+                (UpdateType::Preserve, None) => {
+                    warn!(
+                        "preserving entity {} that never existed, dropping the update",
+                        entity.entity_index
+                    );
+                    return false;
+                }
+                (UpdateType::Leave | UpdateType::Delete, None) => return false,
+                (UpdateType::Leave | UpdateType::Delete, Some(known))
+                    if known.state == EntityState::Deleted =>
+                {
+                    return false
+                }
+                _ => {}
             }
-            ent_message.removed_entities.retain(|id| {
-                if self.known_entities.borrow().contains(&id) {
-                    // just not deleting makes the demo play, but with some ERROR entities
-                    // having a Delete or Leave makes it crash further in the demo
-
-                    // warn!("inserting delete for {}", id);
-                    // only entity_index and update_type is used
-                    // ent_message.entities.push(PacketEntity {
-                    //     entity_index: *id,
-                    //     server_class: 0.into(),
-                    //     props: vec![],
-                    //     in_pvs: false,
-                    //     update_type: UpdateType::Leave,
-                    //     serial_number: 0,
-                    //     delay: None,
-                    //     delta: None,
-                    //     baseline_index: 0,
-                    // });
-                    // self.deferred_delete.borrow_mut().push(*id);
-                    false
-                } else {
+            track(&mut entities, entity);
+            true
+        });
+
+        let updated: BTreeSet<EntityId> = message
+            .entities
+            .iter()
+            .map(|entity| entity.entity_index)
+            .collect();
+        let mut leaving = Vec::new();
+        message.removed_entities.retain(|id| {
+            if updated.contains(id) {
+                return false;
+            }
+            match entities.get_mut(id) {
+                Some(known) if known.state == EntityState::Dormant => {
+                    known.state = EntityState::Deleted;
                     true
                 }
-            });
-            ent_message.entities.sort_by(|a, b| a.entity_index.cmp(&b.entity_index));
-            ent_message.removed_entities.extend(deferred_deletes);
-            ent_message.removed_entities.sort();
+                Some(known) if known.state == EntityState::InPvs => {
+                    // deleting an entity that is still in the PVS crashes the client later on
+                    warn!(
+                        "removing entity {} while in the PVS, removing it after it leaves",
+                        id
+                    );
+                    known.state = EntityState::Dormant;
+                    leaving.push(leave(*id, known));
+                    pending_removals.insert(*id);
+                    false
+                }
+                _ => false,
+            }
+        });
+        message.removed_entities.sort();
+        message.removed_entities.dedup();
+        if !leaving.is_empty() {
+            message.entities.extend(leaving);
+            message.entities.sort_by_key(|entity| entity.entity_index);
+        }
+    }
+
+    fn observe(&self, message: &PacketEntitiesMessage) {
+        let mut entities = self.entities.borrow_mut();
+        let mut pending_removals = self.pending_removals.borrow_mut();
+        if message.delta.is_none() {
+            for entity in entities.values_mut() {
+                entity.state = EntityState::Deleted;
+            }
+            pending_removals.clear();
+        }
+        for entity in &message.entities {
+            pending_removals.remove(&entity.entity_index);
+            track(&mut entities, entity);
+        }
+        for id in &message.removed_entities {
+            if let Some(known) = entities.get_mut(id) {
+                known.state = EntityState::Deleted;
+            }
+        }
+    }
+}
+
+/// Update what the client knows about an entity after an update
+fn track(entities: &mut BTreeMap<EntityId, EntityLifecycle>, entity: &PacketEntity) {
+    match entity.update_type {
+        UpdateType::Enter => {
+            entities.insert(
+                entity.entity_index,
+                EntityLifecycle {
+                    class: entity.server_class,
+                    serial: entity.serial_number,
+                    baseline: entity.baseline_index,
+                    state: EntityState::InPvs,
+                },
+            );
+        }
+        UpdateType::Preserve => {
+            if let Some(known) = entities.get_mut(&entity.entity_index) {
+                known.state = EntityState::InPvs;
+            }
+        }
+        UpdateType::Leave => {
+            if let Some(known) = entities.get_mut(&entity.entity_index) {
+                known.state = EntityState::Dormant;
+            }
+        }
+        UpdateType::Delete => {
+            if let Some(known) = entities.get_mut(&entity.entity_index) {
+                known.state = EntityState::Deleted;
+            }
+        }
+    }
+}
+
+/// A `Leave` update for a known entity
+fn leave(entity_index: EntityId, known: &EntityLifecycle) -> PacketEntity {
+    PacketEntity {
+        server_class: known.class,
+        entity_index,
+        props: Vec::new(),
+        in_pvs: false,
+        update_type: UpdateType::Leave,
+        serial_number: known.serial,
+        delay: None,
+        delta: None,
+        baseline_index: known.baseline,
+    }
+}
+
+/// Turn an update into an `Enter` with the full props from the baseline
+fn enter(entity: &mut PacketEntity, serial: u32, baseline: usize, state: &ParserState) {
+    entity.update_type = UpdateType::Enter;
+    entity.serial_number = serial;
+    entity.baseline_index = baseline;
+    entity.in_pvs = true;
+    let mut props = entity.get_baseline_props(state).into_owned();
+    for prop in take(&mut entity.props) {
+        match props
+            .iter_mut()
+            .find(|existing| existing.identifier == prop.identifier)
+        {
+            Some(existing) => *existing = prop,
+            None => props.push(prop),
+        }
+    }
+    entity.props = props;
+}
+
+impl MessageMutator for FixEntityConsistency {
+    fn mutate_message(&self, message: &mut Message, state: &ParserState) {
+        if let Message::PacketEntities(ent_message) = message {
+            self.fix(ent_message, state);
+        }
+    }
+
+    fn observe_message(&self, message: &Message, _state: &ParserState) {
+        if let Message::PacketEntities(ent_message) = message {
+            self.observe(ent_message);
         }
     }
 }
@@ -1,20 +1,31 @@
 use std::mem::take;
+use tf_demo_parser::demo::header::Header;
 use tf_demo_parser::demo::message::Message;
 use tf_demo_parser::demo::packet::Packet;
 use tf_demo_parser::ParserState;
 
 pub trait PacketMutator {
     fn mutate_packet(&self, packet: &mut Packet, state: &ParserState);
+
+    /// Follow a packet that is written without being mutated, like the synced state of a cut
+    fn observe_packet(&self, _packet: &Packet, _state: &ParserState) {}
 }
 
 pub trait MessageMutator {
     fn mutate_message(&self, message: &mut Message, state: &ParserState);
+
+    /// Follow a message that is written without being mutated, for mutators that track state
+    fn observe_message(&self, _message: &Message, _state: &ParserState) {}
 }
 
 pub trait MessageFilter {
     fn filter(&self, message: &Message) -> bool;
 }
 
+pub trait HeaderMutator {
+    fn mutate_header(&self, header: &mut Header);
+}
+
 pub struct PacketMessageMutator<T: MessageMutator> {
     pub mutator: T,
 }
@@ -31,6 +42,15 @@ impl<T: MessageMutator> PacketMutator for PacketMessageMutator<T> {
             _ => {}
         }
     }
+
+    fn observe_packet(&self, packet: &Packet, state: &ParserState) {
+        if let Packet::Message(msg_packet) | Packet::Signon(msg_packet) = packet {
+            msg_packet
+                .messages
+                .iter()
+                .for_each(|msg| self.mutator.observe_message(msg, state));
+        }
+    }
 }
 
 impl<F: Fn(&mut Packet)> PacketMutator for F {
@@ -82,9 +102,16 @@ impl<F: Fn(&Message) -> bool> MessageFilter for F {
     }
 }
 
+impl<F: Fn(&mut Header)> HeaderMutator for F {
+    fn mutate_header(&self, header: &mut Header) {
+        self(header)
+    }
+}
+
 #[derive(Default)]
 pub struct MutatorList {
     mutators: Vec<Box<dyn PacketMutator>>,
+    header_mutators: Vec<Box<dyn HeaderMutator>>,
 }
 
 impl MutatorList {
@@ -105,6 +132,16 @@ impl MutatorList {
         self.mutators
             .push(Box::new(PacketMessageFilter::from(filter)))
     }
+
+    pub fn push_header_mutator<M: HeaderMutator + 'static>(&mut self, mutator: M) {
+        self.header_mutators.push(Box::new(mutator))
+    }
+
+    pub fn mutate_header(&self, header: &mut Header) {
+        for mutator in self.header_mutators.iter() {
+            mutator.mutate_header(header);
+        }
+    }
 }
 
 impl PacketMutator for MutatorList {
@@ -113,4 +150,10 @@ impl PacketMutator for MutatorList {
             mutator.mutate_packet(packet, state);
         }
     }
+
+    fn observe_packet(&self, packet: &Packet, state: &ParserState) {
+        for mutator in self.mutators.iter() {
+            mutator.observe_packet(packet, state);
+        }
+    }
 }
@@ -1,33 +1,147 @@
+use crate::anchor::EventRange;
+use crate::anonymize::{anonymize, AnonymizeMapping, AnonymizeOptions};
+use crate::chat::{filter_chat, ChatFilterOptions};
+use crate::clean::remove_messages;
+use crate::director::AutoDirectorOptions;
+use crate::idle::IdleOptions;
+use crate::metadata::{rewrite_metadata, MetadataOptions};
+use crate::packets::PacketKind;
+use crate::server_ticks::ServerTickRange;
+use crate::sounds::{remove_sounds, SoundOptions};
+use crate::voice::{remove_voice, VoiceOptions};
 use crate::{clean_demo, strip_cond, unlock_pov, MutatorList};
 use serde::{Deserialize, Serialize};
 use tf_demo_parser::demo::data::DemoTick;
 use tf_demo_parser::demo::message::packetentities::EntityId;
-use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::message::usermessage::UserMessageType;
+use tf_demo_parser::demo::message::MessageType;
+use tf_demo_parser::{Demo, ParseError};
 
-#[derive(Debug, Serialize, Deserialize, Default)]
+/// Net messages the demo can't be played without
+const ESSENTIAL_MESSAGES: &[MessageType] = &[
+    MessageType::PacketEntities,
+    MessageType::NetTick,
+    MessageType::CreateStringTable,
+    MessageType::UpdateStringTable,
+    MessageType::ServerInfo,
+    MessageType::ClassInfo,
+];
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct EditOptions {
     pub unlock_pov: bool,
     #[serde(default)]
     pub remove_conditions: Vec<CondOptions>,
     #[serde(default)]
     pub cut: Option<TickRange>,
+    /// How the ticks in a cut are numbered
+    #[serde(default)]
+    pub tick_numbering: TickNumbering,
+    /// Cut around events in the demo, takes precedence over `cut`
+    ///
+    /// The edit fails if the events aren't in the demo
+    #[serde(default)]
+    pub cut_event: Option<EventRange>,
+    /// Cut by server ticks instead of demo ticks, so cuts from different demos of the same match line up
+    ///
+    /// Takes precedence over `cut` and can't be combined with `cut_event`. If the demo only covers part of the range
+    /// that part is cut and an adjustment is reported, the edit fails if the demo doesn't cover any of it
+    #[serde(default)]
+    pub cut_server_ticks: Option<ServerTickRange>,
+    #[serde(default)]
+    pub auto_director: Option<AutoDirectorOptions>,
+    #[serde(default)]
+    pub anonymize: Option<AnonymizeOptions>,
+    #[serde(default)]
+    pub filter_chat: Option<ChatFilterOptions>,
+    #[serde(default)]
+    pub remove_voice: Option<VoiceOptions>,
+    #[serde(default)]
+    pub remove_sounds: Option<SoundOptions>,
+    /// Packet types to keep that would be dropped by default
+    ///
+    /// Without cutting `ConsoleCmd` and `UserCmd` packets are dropped by default, when cutting only `ConsoleCmd` is
+    #[serde(default)]
+    pub keep_packet_types: Vec<PacketKind>,
+    /// Packet types to drop in addition to the defaults
+    ///
+    /// Only `SyncTick`, `ConsoleCmd` and `UserCmd` packets can be dropped, the demo can't be played without the others
+    #[serde(default)]
+    pub drop_packet_types: Vec<PacketKind>,
+    #[serde(default)]
+    pub metadata: Option<MetadataOptions>,
+    /// Embed the source demo and edit options into the output, see `read_provenance`
+    #[serde(default)]
+    pub embed_provenance: bool,
+    /// User message types to remove in addition to `CloseCaption`, and `VGuiMenu` when unlocking the pov
+    #[serde(default)]
+    pub remove_user_messages: Vec<UserMessageType>,
+    /// Net message types to remove
+    ///
+    /// Messages that set up the entities, string tables or server info can't be removed
+    #[serde(default)]
+    pub remove_messages: Vec<MessageType>,
+    /// Cut out pauses, warmup and humiliation
+    #[serde(default)]
+    pub remove_idle: Option<IdleOptions>,
+    /// Tick ranges to cut out of the demo, the parts around them are joined the same way as when removing idle time
+    ///
+    /// The edit fails if a range ends before it starts or the ranges overlap
+    #[serde(default)]
+    pub remove_ranges: Vec<TickRange>,
+    /// Skip packets that can't be decoded instead of failing the edit
+    ///
+    /// Skipped packets are copied to the output undecoded when that's safe and dropped otherwise,
+    /// see `EditReport::skipped`
+    #[serde(default)]
+    pub lenient: bool,
 }
 
 impl EditOptions {
-    pub fn as_mutator(&self, spectator_id: EntityId) -> MutatorList {
+    pub fn as_mutator(
+        &self,
+        demo: &Demo,
+        spectator_id: EntityId,
+    ) -> Result<MutatorList, ParseError> {
         let mut mutators = MutatorList::new();
 
-        mutators.push_message_mutator(|message: &mut Message| {
-            if let Message::ServerInfo(info) = message {
-                info.server_name = format!("{} - Edited", info.server_name);
-            }
-        });
-
         clean_demo(&mut mutators);
 
+        remove_messages(
+            &mut mutators,
+            self.removed_messages(),
+            self.removed_user_messages(),
+        );
+
+        if let Some(chat_options) = &self.filter_chat {
+            filter_chat(&mut mutators, chat_options.clone());
+        }
+
+        if let Some(voice_options) = &self.remove_voice {
+            remove_voice(&mut mutators, voice_options.clone());
+        }
+
+        if let Some(sound_options) = &self.remove_sounds {
+            remove_sounds(&mut mutators, sound_options.clone());
+        }
+
+        if let Some(anonymize_options) = &self.anonymize {
+            anonymize(
+                &mut mutators,
+                AnonymizeMapping::new(demo, anonymize_options)?,
+            );
+        }
+
+        // after anonymizing, the anonymizer only recognizes names that weren't rewritten by a template
+        rewrite_metadata(
+            &mut mutators,
+            self.metadata.clone().unwrap_or_default(),
+            self.cut,
+        );
+
         for cond_options in self.remove_conditions.iter() {
             let entity = if cond_options.entity > 0 {
-                Some(EntityId::from(cond_options.entity))
+                Some(cond_options.entity)
             } else {
                 None
             };
@@ -38,7 +152,77 @@ impl EditOptions {
             unlock_pov(&mut mutators, spectator_id);
         }
 
-        mutators
+        Ok(mutators)
+    }
+
+    /// Reject options that would produce a demo that can't be played
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.cut_server_ticks.is_some() && self.cut_event.is_some() {
+            return Err("Can't cut by both server ticks and an event".into());
+        }
+        if let Some(cut) = self.cut.filter(|cut| cut.from > cut.to) {
+            return Err(format!(
+                "The cut ends at tick {} before it starts at tick {}",
+                cut.to, cut.from
+            ));
+        }
+        if let Some(range) = self
+            .remove_ranges
+            .iter()
+            .find(|range| range.from > range.to)
+        {
+            return Err(format!(
+                "The removed range ends at tick {} before it starts at tick {}",
+                range.to, range.from
+            ));
+        }
+        let mut removed = self.remove_ranges.clone();
+        removed.sort_by_key(|range| range.from);
+        if let Some(pair) = removed.windows(2).find(|pair| pair[1].from <= pair[0].to) {
+            return Err(format!(
+                "The removed ranges {}-{} and {}-{} overlap",
+                pair[0].from, pair[0].to, pair[1].from, pair[1].to
+            ));
+        }
+        if let Some(kind) = self.drop_packet_types.iter().find(|kind| !kind.can_drop()) {
+            return Err(format!("{:?} packets can't be dropped", kind));
+        }
+        if let Some(message_type) = self
+            .remove_messages
+            .iter()
+            .find(|message_type| ESSENTIAL_MESSAGES.contains(message_type))
+        {
+            return Err(format!("{:?} messages can't be removed", message_type));
+        }
+        Ok(())
+    }
+
+    /// Whether the edit has to go through the cutting machinery
+    pub fn cuts(&self) -> bool {
+        self.cut.is_some()
+            || self.cut_event.is_some()
+            || self.cut_server_ticks.is_some()
+            || self.remove_idle.is_some()
+            || !self.remove_ranges.is_empty()
+    }
+
+    fn removed_messages(&self) -> Vec<MessageType> {
+        let mut types = self.remove_messages.clone();
+        if self.remove_idle.as_ref().map(|idle| idle.pauses) == Some(true) {
+            // pausing in the middle of the output would freeze playback
+            types.push(MessageType::SetPause);
+        }
+        types
+    }
+
+    fn removed_user_messages(&self) -> Vec<UserMessageType> {
+        let mut types = vec![UserMessageType::CloseCaption];
+        if self.unlock_pov {
+            // the menus would cover the view of the unlocked pov
+            types.push(UserMessageType::VGuiMenu);
+        }
+        types.extend(self.remove_user_messages.iter().copied());
+        types
     }
 }
 
@@ -48,7 +232,20 @@ pub struct CondOptions {
     mask: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone)]
+/// How the ticks in a cut are numbered
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TickNumbering {
+    /// Start the cut at tick 0
+    #[default]
+    Zero,
+    /// Keep the tick numbers from the source demo
+    Original,
+    /// Start the cut at the given tick
+    Base(DemoTick),
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq, Eq)]
 pub struct TickRange {
     pub from: DemoTick,
     pub to: DemoTick,
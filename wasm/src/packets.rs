@@ -0,0 +1,269 @@
+use crate::EditOptions;
+use bitbuffer::{BitRead, BitWrite, BitWriteStream, LittleEndian};
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::packet::{Packet, PacketType};
+use tf_demo_parser::demo::parser::{Encode, RawPacketStream};
+use tf_demo_parser::{ParseError, ParserState, Stream};
+
+pub(crate) const HEADER_SIZE: usize = 1072;
+
+/// Size of the command info and sequence numbers before the data of a message packet
+const MESSAGE_META_SIZE: usize = 84;
+
+/// Packet types that can be kept or dropped from the output
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PacketKind {
+    Signon,
+    Message,
+    SyncTick,
+    ConsoleCmd,
+    UserCmd,
+    DataTables,
+    StringTables,
+}
+
+impl PacketKind {
+    /// Whether the demo still plays without packets of this type
+    pub fn can_drop(&self) -> bool {
+        matches!(
+            self,
+            PacketKind::SyncTick | PacketKind::ConsoleCmd | PacketKind::UserCmd
+        )
+    }
+}
+
+impl From<PacketKind> for PacketType {
+    fn from(kind: PacketKind) -> Self {
+        match kind {
+            PacketKind::Signon => PacketType::Signon,
+            PacketKind::Message => PacketType::Message,
+            PacketKind::SyncTick => PacketType::SyncTick,
+            PacketKind::ConsoleCmd => PacketType::ConsoleCmd,
+            PacketKind::UserCmd => PacketType::UserCmd,
+            PacketKind::DataTables => PacketType::DataTables,
+            PacketKind::StringTables => PacketType::StringTables,
+        }
+    }
+}
+
+/// Writes the output packets and keeps track of what was written and removed,
+/// so the header can be back-patched afterwards
+pub struct PacketWriter {
+    dropped: Vec<PacketType>,
+    position: usize,
+    /// The number of bytes written before the first message packet
+    signon_length: Option<usize>,
+    frames: u32,
+    last_tick: DemoTick,
+}
+
+impl PacketWriter {
+    /// `defaults` are the packet types dropped when not explicitly kept
+    pub fn new(options: &EditOptions, defaults: &[PacketType]) -> Self {
+        let dropped = defaults
+            .iter()
+            .copied()
+            .filter(|ty| {
+                !options
+                    .keep_packet_types
+                    .iter()
+                    .any(|kind| PacketType::from(*kind) == *ty)
+            })
+            .chain(
+                options
+                    .drop_packet_types
+                    .iter()
+                    .copied()
+                    .map(PacketType::from),
+            )
+            .collect();
+        PacketWriter {
+            dropped,
+            position: HEADER_SIZE,
+            signon_length: None,
+            frames: 0,
+            last_tick: DemoTick::default(),
+        }
+    }
+
+    pub fn keeps(&self, packet_type: PacketType) -> bool {
+        !self.dropped.contains(&packet_type)
+    }
+
+    /// Write the packet if its type is retained
+    pub fn encode(
+        &mut self,
+        packet: &Packet,
+        stream: &mut BitWriteStream<LittleEndian>,
+        state: &ParserState,
+    ) {
+        if self.keeps(packet.packet_type()) {
+            self.write(packet, stream, state);
+        }
+    }
+
+    /// Write the packet regardless of its type
+    pub fn write(
+        &mut self,
+        packet: &Packet,
+        stream: &mut BitWriteStream<LittleEndian>,
+        state: &ParserState,
+    ) {
+        self.start_packet(packet.packet_type());
+        let start = stream.bit_len();
+        packet.encode(stream, state).unwrap();
+        self.position += (stream.bit_len() - start) / 8;
+        self.last_tick = self.last_tick.max(packet.tick());
+    }
+
+    /// Copy a packet that couldn't be decoded to the output as-is, with the tick changed to `tick`
+    pub fn write_undecodable(
+        &mut self,
+        packet: &UndecodablePacket,
+        tick: DemoTick,
+        stream: &mut BitWriteStream<LittleEndian>,
+    ) {
+        let Some(body) = &packet.body else {
+            return;
+        };
+        self.start_packet(packet.packet_type);
+        let start = stream.bit_len();
+        packet.packet_type.write(stream).unwrap();
+        tick.write(stream).unwrap();
+        stream.write_bits(body).unwrap();
+        self.position += (stream.bit_len() - start) / 8;
+        self.last_tick = self.last_tick.max(tick);
+    }
+
+    fn start_packet(&mut self, packet_type: PacketType) {
+        if packet_type == PacketType::Message {
+            self.frames += 1;
+            // the signon is everything before the first frame
+            self.signon_length
+                .get_or_insert(self.position - HEADER_SIZE);
+        }
+    }
+
+    /// Set the signon length, frame count, tick count and duration to match the written packets
+    ///
+    /// `first_tick` is the tick the demo starts at, the tick count is the number of ticks from there
+    pub fn patch_header(&self, header: &mut Header, interval_per_tick: f32, first_tick: DemoTick) {
+        let interval_per_tick = if interval_per_tick > 0.0 {
+            interval_per_tick
+        } else if header.ticks > 0 {
+            header.duration / header.ticks as f32
+        } else {
+            0.0
+        };
+        header.signon = self.signon_length.unwrap_or(self.position - HEADER_SIZE) as u32;
+        header.frames = self.frames;
+        header.ticks = u32::from(self.last_tick).saturating_sub(first_tick.into());
+        header.duration = header.ticks as f32 * interval_per_tick;
+    }
+}
+
+/// A packet from the source demo that couldn't be decoded
+pub struct UndecodablePacket<'a> {
+    pub packet_type: PacketType,
+    pub tick: DemoTick,
+    /// The raw packet data after the tick, `None` if the end of the packet couldn't be found
+    body: Option<Stream<'a>>,
+    pub error: ParseError,
+}
+
+impl UndecodablePacket<'_> {
+    /// Whether the packet can be copied to the output without knowing what's in it
+    ///
+    /// Packets that set up tables and baselines can't be, since the following packets are decoded using them.
+    /// Message packets can't be either, the entity updates in them have to be kept consistent with the output.
+    pub fn can_copy(&self) -> bool {
+        self.body.is_some()
+            && matches!(
+                self.packet_type,
+                PacketType::ConsoleCmd | PacketType::UserCmd | PacketType::SyncTick
+            )
+    }
+}
+
+pub enum NextPacket<'a> {
+    Packet(Packet<'a>),
+    Undecodable(UndecodablePacket<'a>),
+    End,
+}
+
+/// Read the next packet from the source demo
+///
+/// In lenient mode packets that can't be decoded are returned undecoded and reading continues after them,
+/// as long as the end of the packet can be found. Otherwise any packet that can't be decoded is returned as error.
+pub fn next_packet<'a>(
+    packets: &mut RawPacketStream<'a>,
+    stream: &Stream<'a>,
+    state: &ParserState,
+    lenient: bool,
+) -> Result<NextPacket<'a>, ParseError> {
+    if !lenient {
+        return Ok(match packets.next(state)? {
+            Some(packet) => NextPacket::Packet(packet),
+            None => NextPacket::End,
+        });
+    }
+
+    let start = packets.pos();
+    Ok(match packets.next(state) {
+        Ok(Some(packet)) => NextPacket::Packet(packet),
+        Ok(None) => NextPacket::End,
+        Err(error) => {
+            let mut raw = stream.clone();
+            if raw.set_pos(start).is_err() {
+                return Ok(NextPacket::End);
+            }
+            let Ok(packet_type) = PacketType::read(&mut raw) else {
+                return Ok(NextPacket::End);
+            };
+            let Ok(tick) = raw.read() else {
+                return Ok(NextPacket::End);
+            };
+            let body = read_body(&mut raw, packet_type).ok();
+            if body.is_some() {
+                // the stream stopped at the error, continue after the packet instead
+                *packets = RawPacketStream::new(raw);
+            }
+            NextPacket::Undecodable(UndecodablePacket {
+                packet_type,
+                tick,
+                body,
+                error,
+            })
+        }
+    })
+}
+
+/// Read the raw data of a packet after its type and tick, without decoding it
+fn read_body<'a>(
+    stream: &mut Stream<'a>,
+    packet_type: PacketType,
+) -> Result<Stream<'a>, ParseError> {
+    let start = stream.pos();
+    let length: u32 = match packet_type {
+        PacketType::Signon | PacketType::Message => {
+            stream.skip_bits(MESSAGE_META_SIZE * 8)?;
+            stream.read()?
+        }
+        PacketType::UserCmd => {
+            stream.skip_bits(32)?;
+            stream.read()?
+        }
+        PacketType::ConsoleCmd | PacketType::DataTables | PacketType::StringTables => {
+            stream.read()?
+        }
+        PacketType::SyncTick => 0,
+        PacketType::Stop => return Err(ParseError::InvalidDemo("undecodable stop packet")),
+    };
+    stream.skip_bits(length as usize * 8)?;
+    let end = stream.pos();
+    stream.set_pos(start)?;
+    Ok(stream.read_bits(end - start)?)
+}
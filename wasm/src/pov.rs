@@ -1,7 +1,6 @@
 use crate::mutate::{MessageMutator, MutatorList};
 use std::cell::Cell;
 use tf_demo_parser::demo::message::packetentities::{EntityId, PacketEntity, UpdateType};
-use tf_demo_parser::demo::message::usermessage::UserMessage;
 use tf_demo_parser::demo::message::Message;
 use tf_demo_parser::demo::packet::Packet;
 use tf_demo_parser::ParserState;
@@ -48,9 +47,7 @@ impl MessageMutator for AddStvEntity {
                         delta: None,
                         baseline_index: 0,
                     });
-                    ent_message
-                        .entities
-                        .sort_by(|a, b| a.entity_index.cmp(&b.entity_index));
+                    ent_message.entities.sort_by_key(|a| a.entity_index);
                     self.added.set(true);
                 }
             }
@@ -65,9 +62,6 @@ pub fn unlock_pov(mutators: &mut MutatorList, spectator_id: EntityId) {
         }
     });
     mutators.push_message_filter(|message: &Message| !matches!(message, Message::SetView(_)));
-    mutators.push_message_filter(|message: &Message| {
-        !matches!(message, Message::UserMessage(UserMessage::VGuiMenu(_)))
-    });
     mutators.push_message_mutator(|message: &mut Message| {
         if let Message::ServerInfo(info) = message {
             info.stv = true;
@@ -0,0 +1,245 @@
+use crate::{EditOptions, MetadataOptions};
+use bitbuffer::BitRead;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::packet::consolecmd::ConsoleCmdPacket;
+use tf_demo_parser::demo::packet::Packet;
+use tf_demo_parser::demo::parser::{DemoHandler, RawPacketStream};
+use tf_demo_parser::Demo;
+
+const PREFIX: &str = "// demo-edit provenance";
+const REDACTED: &str = "redacted";
+/// Keep the commands well below the engine's command length limit
+const CHUNK_SIZE: usize = 960;
+
+/// Where an edited demo came from, embedded in the demo as console comments
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Provenance {
+    /// Version of the editor
+    pub editor_version: String,
+    /// FNV-1a hash of the source demo
+    pub input_hash: String,
+    /// The header fields of the source demo, the server and nick are redacted when anonymizing or rewriting them
+    pub server: String,
+    pub nick: String,
+    pub map: String,
+    pub ticks: u32,
+    pub duration: f32,
+    /// The tick in the source demo that corresponds with the start of the edited demo
+    pub tick_offset: u32,
+    pub options: EditOptions,
+}
+
+impl Provenance {
+    /// `header` is the header of the source demo
+    ///
+    /// The options that identify players, the anonymize seed and the metadata templates are redacted
+    /// in the embedded options, they can be used to recover the original names.
+    pub fn new(
+        input: &[u8],
+        header: &Header,
+        options: &EditOptions,
+        tick_offset: DemoTick,
+    ) -> Self {
+        let hide_names = options.anonymize.is_some() || options.metadata.is_some();
+        let redact = |value: &String| {
+            if hide_names {
+                String::from(REDACTED)
+            } else {
+                value.clone()
+            }
+        };
+        Provenance {
+            editor_version: env!("CARGO_PKG_VERSION").to_string(),
+            input_hash: input_hash(input),
+            server: redact(&header.server),
+            nick: redact(&header.nick),
+            map: header.map.clone(),
+            ticks: header.ticks,
+            duration: header.duration,
+            tick_offset: tick_offset.into(),
+            options: redact_options(options),
+        }
+    }
+
+    /// Encode the provenance as console command packets
+    ///
+    /// The data is hex encoded so it can't contain anything the console would interpret.
+    pub fn packets(&self, tick: DemoTick) -> Vec<Packet<'static>> {
+        let json = serde_json::to_string(self).expect("failed to serialize provenance");
+        let hex: String = json.bytes().map(|byte| format!("{:02x}", byte)).collect();
+        let chunks: Vec<&str> = hex
+            .as_bytes()
+            .chunks(CHUNK_SIZE)
+            .map(|chunk| std::str::from_utf8(chunk).unwrap())
+            .collect();
+        let count = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                Packet::ConsoleCmd(ConsoleCmdPacket {
+                    tick,
+                    command: format!("{} {}/{} {}", PREFIX, index + 1, count, chunk),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Redact the options that identify players
+fn redact_options(options: &EditOptions) -> EditOptions {
+    let mut options = options.clone();
+    if let Some(anonymize) = options.anonymize.as_mut() {
+        anonymize.seed = String::from(REDACTED);
+    }
+    if let Some(metadata) = options.metadata.as_mut() {
+        *metadata = MetadataOptions::default();
+    }
+    if let Some(chat) = options.filter_chat.as_mut() {
+        for player in chat.players.iter_mut() {
+            *player = String::from(REDACTED);
+        }
+    }
+    if let Some(director) = options.auto_director.as_mut() {
+        for priority in director.priorities.iter_mut() {
+            priority.steam_id = String::from(REDACTED);
+        }
+    }
+    options
+}
+
+/// FNV-1a hash of the demo, for recognizing the source demo
+pub(crate) fn input_hash(input: &[u8]) -> String {
+    let hash = input.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Parse a single provenance command into its part number, part count and data
+fn parse_command(command: &str) -> Option<(usize, usize, &str)> {
+    let rest = command.strip_prefix(PREFIX)?.trim_start();
+    let (part, data) = rest.split_once(' ')?;
+    let (index, count) = part.split_once('/')?;
+    Some((index.parse().ok()?, count.parse().ok()?, data))
+}
+
+/// Read the provenance embedded in an edited demo, if any
+pub fn read_provenance(input: &[u8]) -> Option<Provenance> {
+    let demo = Demo::new(input);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).ok()?;
+
+    let mut packets = RawPacketStream::new(stream);
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+
+    let mut hex = String::new();
+    while let Some(packet) = packets.next(&handler.state_handler).ok()? {
+        if let Packet::ConsoleCmd(cmd) = &packet {
+            if let Some((index, count, data)) = parse_command(&cmd.command) {
+                hex.push_str(data);
+                if index == count {
+                    break;
+                }
+            }
+        }
+        handler.handle_packet(packet).ok()?;
+    }
+
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{edit, DemoBuilder};
+
+    const TEMPLATE: &[u8] = include_bytes!("../tests/data/template.dem");
+
+    fn demo() -> Vec<u8> {
+        let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+        builder
+            .tick(1u32.into())
+            .enter(2u32.into(), "CTFPlayer", [])
+            .player(2u32.into(), 10, "player", "[U:1:1234]")
+            .tick(20u32.into());
+        builder.build()
+    }
+
+    fn options(extra: serde_json::Value) -> EditOptions {
+        let mut options = serde_json::json!({
+            "unlock_pov": false,
+            "embed_provenance": true,
+        });
+        options
+            .as_object_mut()
+            .unwrap()
+            .extend(extra.as_object().unwrap().clone());
+        serde_json::from_value(options).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = demo();
+        let header = Header::read(&mut Demo::new(&input).get_stream()).unwrap();
+        let options = options(serde_json::json!({
+            "cut": {"from": 5, "to": 15},
+        }));
+        let output = edit(&input, options).unwrap();
+
+        let provenance = read_provenance(&output).unwrap();
+        assert_eq!(provenance.editor_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(provenance.input_hash, input_hash(&input));
+        assert_eq!(provenance.server, header.server);
+        assert_eq!(provenance.nick, header.nick);
+        assert_eq!(provenance.map, header.map);
+        assert_eq!(provenance.ticks, header.ticks);
+        assert_eq!(provenance.duration, header.duration);
+        assert_eq!(provenance.tick_offset, 5);
+        assert!(provenance.options.embed_provenance);
+        assert_eq!(provenance.options.cut.unwrap().to, 15u32);
+    }
+
+    #[test]
+    fn no_provenance_without_embedding() {
+        let input = demo();
+        let output = edit(
+            &input,
+            options(serde_json::json!({"embed_provenance": false})),
+        )
+        .unwrap();
+        assert!(read_provenance(&output).is_none());
+    }
+
+    #[test]
+    fn identities_are_redacted() {
+        let input = demo();
+        let options = options(serde_json::json!({
+            "anonymize": {"seed": "secret"},
+            "metadata": {"nick": "someone else", "server": "elsewhere"},
+            "filter_chat": {"players": ["player"]},
+            "auto_director": {"priorities": [{"steam_id": "[U:1:1234]", "priority": 2.0}]},
+        }));
+        let output = edit(&input, options).unwrap();
+
+        let provenance = read_provenance(&output).unwrap();
+        assert_eq!(provenance.server, REDACTED);
+        assert_eq!(provenance.nick, REDACTED);
+        let options = provenance.options;
+        assert_eq!(options.anonymize.unwrap().seed, REDACTED);
+        let metadata = options.metadata.unwrap();
+        assert_eq!(metadata.nick, None);
+        assert_eq!(metadata.server, None);
+        assert_eq!(options.filter_chat.unwrap().players, vec![REDACTED]);
+        let priorities = options.auto_director.unwrap().priorities;
+        assert_eq!(priorities[0].steam_id, REDACTED);
+        assert_eq!(priorities[0].priority, 2.0);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::header::interval_per_tick;
+use crate::packets::HEADER_SIZE;
+use crate::patch_header;
+use bitbuffer::{BitRead, BitWrite, BitWriteStream, LittleEndian};
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::packet::stop::StopPacket;
+use tf_demo_parser::demo::packet::PacketType;
+use tf_demo_parser::demo::parser::{DemoHandler, Encode, RawPacketStream};
+use tf_demo_parser::Demo;
+
+/// Details about how much of a damaged demo could be salvaged
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RepairReport {
+    /// Number of packets kept from the input
+    pub packets: u32,
+    /// Number of bytes kept from the input, including the header
+    pub salvaged_bytes: usize,
+    /// Number of bytes at the end of the input that couldn't be read and were dropped
+    pub dropped_bytes: usize,
+    /// Whether the input was missing its `Stop` packet
+    pub added_stop: bool,
+    /// The error that stopped reading the input, if it ended in something other than a partial packet
+    pub error: Option<String>,
+    pub ticks: u32,
+    pub frames: u32,
+    pub duration: f32,
+}
+
+/// Repair a demo from a crashed game or killed server
+///
+/// Packets are read until the first one that can't be read, anything after it is dropped.
+/// A `Stop` packet is appended if the demo doesn't end with one and the header is recomputed from the kept packets.
+///
+/// Fails if the demo doesn't even have a complete header.
+pub fn repair(input: &[u8]) -> Result<(Vec<u8>, RepairReport), String> {
+    let demo = Demo::new(input);
+    let mut stream = demo.get_stream();
+    let mut header =
+        Header::read(&mut stream).map_err(|e| format!("failed to read demo header: {}", e))?;
+
+    let mut packets = RawPacketStream::new(stream);
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+
+    let mut report = RepairReport::default();
+    let mut end = packets.pos() / 8;
+    let mut last_tick = DemoTick::default();
+    let mut stopped = false;
+    loop {
+        let packet = match packets.next(&handler.state_handler) {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) => {
+                report.error = Some(e.to_string());
+                break;
+            }
+        };
+        let packet_type = packet.packet_type();
+        let tick = packet.tick();
+        let packet_end = packets.pos() / 8;
+        if let Err(e) = handler.handle_packet(packet) {
+            report.error = Some(e.to_string());
+            break;
+        }
+        end = packet_end;
+        report.packets += 1;
+        match packet_type {
+            PacketType::Message => report.frames += 1,
+            PacketType::Stop => stopped = true,
+            _ => {}
+        }
+        last_tick = last_tick.max(tick);
+    }
+
+    report.salvaged_bytes = end;
+    report.dropped_bytes = input.len() - end;
+    report.added_stop = !stopped;
+
+    let mut out_buffer = Vec::with_capacity(end + 8);
+    out_buffer.extend_from_slice(&input[..end]);
+    if !stopped {
+        let mut out_stream = BitWriteStream::new(&mut out_buffer, LittleEndian);
+        PacketType::Stop.write(&mut out_stream).unwrap();
+        StopPacket { tick: last_tick }
+            .encode(&mut out_stream, &handler.state_handler)
+            .unwrap();
+    }
+
+    let interval = match handler.state_handler.demo_meta.interval_per_tick {
+        interval if interval > 0.0 => interval,
+        _ => interval_per_tick(&header),
+    };
+    header.signon = header.signon.min(end.saturating_sub(HEADER_SIZE) as u32);
+    header.frames = report.frames;
+    header.ticks = last_tick.into();
+    header.duration = header.ticks as f32 * interval;
+    patch_header(&mut out_buffer, &header);
+
+    report.ticks = header.ticks;
+    report.duration = header.duration;
+    Ok((out_buffer, report))
+}
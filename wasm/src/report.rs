@@ -0,0 +1,69 @@
+use crate::packets::UndecodablePacket;
+use crate::TickRange;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::DemoTick;
+use tf_demo_parser::demo::packet::PacketType;
+
+/// Details about the edited demo and any changes that had to be made to the requested edit
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct EditReport {
+    /// The tick range that was cut, which can differ from the requested range
+    pub cut: Option<TickRange>,
+    /// Idle periods and other ranges that were removed
+    pub removed: Vec<TickRange>,
+    /// Packets that couldn't be decoded and were skipped in lenient mode
+    pub skipped: Vec<SkippedPacket>,
+    /// Human-readable descriptions of the changes made to the requested edit
+    pub adjustments: Vec<String>,
+    /// Human-readable descriptions of problems in the output that can break playback
+    pub integrity_errors: Vec<String>,
+    pub ticks: u32,
+    pub frames: u32,
+    pub duration: f32,
+}
+
+impl EditReport {
+    pub fn adjust(&mut self, adjustment: String) {
+        log::warn!("{}", adjustment);
+        self.adjustments.push(adjustment);
+    }
+
+    /// Record a packet that couldn't be decoded, `copied` is whether it was copied to the output undecoded
+    pub(crate) fn skip(&mut self, packet: &UndecodablePacket, copied: bool) {
+        log::warn!(
+            "{} packet at tick {} can't be decoded ({}), {}",
+            packet.packet_type.as_str(),
+            packet.tick,
+            packet.error,
+            if copied {
+                "copying it as-is"
+            } else {
+                "dropping it"
+            }
+        );
+        self.skipped.push(SkippedPacket {
+            tick: packet.tick,
+            copied,
+            error: packet.error.to_string(),
+        });
+        if !copied && packet.packet_type == PacketType::Message {
+            // later entity updates are deltas that include the changes from the dropped packet
+            let error = format!(
+                "The message packet at tick {} was dropped, the entities after it can differ from the original",
+                packet.tick
+            );
+            log::error!("{}", error);
+            self.integrity_errors.push(error);
+        }
+    }
+}
+
+/// A packet that couldn't be decoded
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SkippedPacket {
+    /// The tick of the packet in the source demo
+    pub tick: DemoTick,
+    /// Whether the packet was copied to the output without decoding it, or dropped
+    pub copied: bool,
+    pub error: String,
+}
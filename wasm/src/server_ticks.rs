@@ -0,0 +1,186 @@
+use crate::{edit_with_report, parse_failed, EditOptions, EditReport, TickRange};
+use bitbuffer::BitRead;
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::data::{DemoTick, ServerTick};
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::packet::Packet;
+use tf_demo_parser::demo::parser::{DemoHandler, RawPacketStream};
+use tf_demo_parser::{Demo, ParseError};
+
+/// A range of server ticks, these are shared between all demos recorded on the same server
+#[derive(Debug, Serialize, Deserialize, Default, Copy, Clone, PartialEq, Eq)]
+pub struct ServerTickRange {
+    pub from: ServerTick,
+    pub to: ServerTick,
+}
+
+/// The demo ticks for a range of server ticks
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ResolvedServerRange {
+    pub ticks: TickRange,
+    /// The part of the server tick range that is in the demo
+    pub covered: ServerTickRange,
+}
+
+/// Find the demo ticks for a range of server ticks
+///
+/// If the demo only covers part of the range, the part that is covered is used,
+/// fails if none of the range is in the demo.
+pub fn resolve_server_range(
+    demo: &Demo,
+    range: ServerTickRange,
+) -> Result<ResolvedServerRange, String> {
+    find_server_ticks(demo, range)
+        .map_err(parse_failed)?
+        .ok_or_else(|| {
+            format!(
+                "Server ticks {} to {} aren't in the demo",
+                range.from, range.to
+            )
+        })
+}
+
+fn find_server_ticks(
+    demo: &Demo,
+    range: ServerTickRange,
+) -> Result<Option<ResolvedServerRange>, ParseError> {
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream)?;
+    let mut packets = RawPacketStream::new(stream);
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+
+    let mut first: Option<(ServerTick, DemoTick)> = None;
+    let mut last: Option<(ServerTick, DemoTick)> = None;
+    while let Some(packet) = packets.next(&handler.state_handler)? {
+        if let Packet::Message(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::NetTick(net_tick) = message {
+                    if net_tick.tick >= range.from && first.is_none() {
+                        first = Some((net_tick.tick, message_packet.tick));
+                    }
+                    if net_tick.tick <= range.to {
+                        last = Some((net_tick.tick, message_packet.tick));
+                    }
+                }
+            }
+        }
+        handler.handle_packet(packet)?;
+    }
+
+    Ok(match (first, last) {
+        (Some((first_server, from)), Some((last_server, to))) if from <= to => {
+            Some(ResolvedServerRange {
+                ticks: TickRange { from, to },
+                covered: ServerTickRange {
+                    from: range.from.max(first_server),
+                    to: range.to.min(last_server),
+                },
+            })
+        }
+        _ => None,
+    })
+}
+
+/// Cut the same server tick range out of multiple demos of the same match, applying the other edit options to each
+///
+/// Every input gets its own result, a demo that doesn't cover the range doesn't fail the others.
+/// A demo that covers only part of the range is cut to that part, see `EditReport::adjustments`.
+pub fn cut_povs(
+    inputs: &[&[u8]],
+    range: ServerTickRange,
+    options: &EditOptions,
+) -> Vec<Result<(Vec<u8>, EditReport), String>> {
+    inputs
+        .iter()
+        .map(|input| {
+            let options = EditOptions {
+                cut_server_ticks: Some(range),
+                ..options.clone()
+            };
+            edit_with_report(input, options, None)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DemoBuilder;
+
+    const TEMPLATE: &[u8] = include_bytes!("../tests/data/template.dem");
+
+    /// A demo with a frame for every demo tick up to 50, starting at `first_server_tick`
+    fn demo(first_server_tick: u32) -> Vec<u8> {
+        let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+        builder.first_server_tick(first_server_tick.into());
+        for tick in 0..=50u32 {
+            builder.tick(tick.into());
+        }
+        builder.build()
+    }
+
+    fn server_range(from: u32, to: u32) -> ServerTickRange {
+        ServerTickRange {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    fn tick_range(from: u32, to: u32) -> TickRange {
+        TickRange {
+            from: from.into(),
+            to: to.into(),
+        }
+    }
+
+    #[test]
+    fn aligns_demos_starting_at_different_server_ticks() {
+        let early = demo(100);
+        let late = demo(120);
+        let range = server_range(130, 140);
+
+        let early = resolve_server_range(&Demo::new(&early), range).unwrap();
+        let late = resolve_server_range(&Demo::new(&late), range).unwrap();
+        assert_eq!(early.ticks, tick_range(30, 40));
+        assert_eq!(late.ticks, tick_range(10, 20));
+        assert_eq!(early.covered, range);
+        assert_eq!(late.covered, range);
+    }
+
+    #[test]
+    fn uses_the_covered_part_of_the_range() {
+        let input = demo(100);
+        let resolved = resolve_server_range(&Demo::new(&input), server_range(90, 120)).unwrap();
+        assert_eq!(resolved.ticks, tick_range(0, 20));
+        assert_eq!(resolved.covered, server_range(100, 120));
+
+        let resolved = resolve_server_range(&Demo::new(&input), server_range(140, 200)).unwrap();
+        assert_eq!(resolved.ticks, tick_range(40, 50));
+        assert_eq!(resolved.covered, server_range(140, 150));
+    }
+
+    #[test]
+    fn fails_outside_the_demo() {
+        let input = demo(100);
+        assert!(resolve_server_range(&Demo::new(&input), server_range(200, 300)).is_err());
+        assert!(resolve_server_range(&Demo::new(&input), server_range(10, 50)).is_err());
+        assert!(resolve_server_range(&Demo::new(&input[..500]), server_range(100, 120)).is_err());
+    }
+
+    #[test]
+    fn cut_povs_rejects_cut_event() {
+        let input = demo(100);
+        let options: EditOptions = serde_json::from_value(serde_json::json!({
+            "unlock_pov": false,
+            "cut_event": {"anchor": {"type": "round_start", "round": 1}}
+        }))
+        .unwrap();
+        let results = cut_povs(&[&input], server_range(110, 120), &options);
+        assert_eq!(
+            results[0].as_ref().unwrap_err(),
+            "Can't cut by both server ticks and an event"
+        );
+    }
+}
@@ -0,0 +1,630 @@
+use crate::mutate::{MutatorList, PacketMutator};
+use bitbuffer::{BitReadBuffer, BitReadStream, BitWrite, BitWriteStream, LittleEndian};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::mem::take;
+use tf_demo_parser::demo::message::voice::ParseSoundsMessage;
+use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::packet::stringtable::{StringTable, StringTableEntry};
+use tf_demo_parser::demo::packet::Packet;
+use tf_demo_parser::{ParserState, Stream};
+
+/// Remove sounds by their path in the `soundprecache` table
+///
+/// Patterns are matched against the full path, `*` matches any number of characters and `?` matches a single one,
+/// for example `music/*` or `*/taunt_*.wav`.
+///
+/// Temp entities aren't affected, none of the temp entities used by tf2 reference the `soundprecache` table.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SoundOptions {
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+impl SoundOptions {
+    fn matches(&self, path: &str) -> bool {
+        let path = normalize_path(path);
+        self.patterns
+            .iter()
+            .any(|pattern| glob_match(&normalize_path(pattern), &path))
+    }
+}
+
+/// Strip the sound characters prefixing the path and normalize the separators and casing
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches(|c| "*#@><^)}$!?&~".contains(c))
+        .replace('\\', "/")
+        .to_lowercase()
+}
+
+/// Sounds that keep playing until they're stopped, there is no way to see the loop points of the wav files
+/// so we go by their name
+const LOOPING_SOUNDS: &[&str] = &[
+    "*loop*",
+    "*_lp.wav",
+    "weapons/minigun_spin.wav",
+    "weapons/medigun_heal.wav",
+];
+
+pub(crate) fn is_looping_sound(path: &str) -> bool {
+    let path = normalize_path(path);
+    LOOPING_SOUNDS
+        .iter()
+        .any(|pattern| glob_match(pattern, &path))
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+const SND_CHANGE_VOL: u32 = 1 << 0;
+const SND_CHANGE_PITCH: u32 = 1 << 1;
+pub(crate) const SND_STOP: u32 = 1 << 2;
+const SND_DELAY: u32 = 1 << 4;
+pub(crate) const SND_STOP_LOOPING: u32 = 1 << 5;
+const ENTITY_BITS: usize = 11;
+const SEQUENCE_BITS: usize = 10;
+const LEVEL_BITS: usize = 9;
+const DELAY_BITS: usize = 13;
+const COORD_BITS: usize = 12;
+
+/// A single sound from a `ParseSounds` message
+///
+/// Values are kept in their encoded form so the sounds can be written back bit-for-bit.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SoundInfo {
+    pub(crate) entity: u32,
+    pub(crate) sound: u32,
+    pub(crate) flags: u32,
+    pub(crate) channel: u32,
+    ambient: bool,
+    pub(crate) sentence: bool,
+    sequence: u32,
+    volume: u32,
+    level: u32,
+    pitch: u32,
+    dsp: u32,
+    delay: i32,
+    origin: [i32; 3],
+}
+
+impl Default for SoundInfo {
+    fn default() -> Self {
+        SoundInfo {
+            entity: 0,
+            sound: 0,
+            flags: 0,
+            channel: 6,
+            ambient: false,
+            sentence: false,
+            sequence: 0,
+            volume: 127,
+            level: 75,
+            pitch: 100,
+            dsp: 0,
+            // encoded form of a 0s delay
+            delay: 100,
+            origin: [0; 3],
+        }
+    }
+}
+
+/// Bit sizes for the fields that changed between protocol versions
+pub(crate) struct SoundFormat {
+    sound_bits: usize,
+    flag_bits: usize,
+    has_dsp: bool,
+}
+
+impl SoundFormat {
+    pub(crate) fn new(protocol: u32) -> Self {
+        SoundFormat {
+            sound_bits: if protocol > 22 { 14 } else { 13 },
+            flag_bits: if protocol > 18 { 11 } else { 9 },
+            has_dsp: protocol > 21,
+        }
+    }
+}
+
+fn read_delta_uint(stream: &mut Stream, bits: usize, delta: u32) -> bitbuffer::Result<u32> {
+    if stream.read_bool()? {
+        stream.read_int(bits)
+    } else {
+        Ok(delta)
+    }
+}
+
+fn read_signed(stream: &mut Stream, bits: usize) -> bitbuffer::Result<i32> {
+    let raw: u32 = stream.read_int(bits)?;
+    let shift = 32 - bits as u32;
+    Ok(((raw << shift) as i32) >> shift)
+}
+
+fn write_delta_uint(
+    stream: &mut BitWriteStream<LittleEndian>,
+    bits: usize,
+    value: u32,
+    delta: u32,
+) -> bitbuffer::Result<()> {
+    stream.write_bool(value != delta)?;
+    if value != delta {
+        stream.write_int(value, bits)?;
+    }
+    Ok(())
+}
+
+fn write_signed(
+    stream: &mut BitWriteStream<LittleEndian>,
+    bits: usize,
+    value: i32,
+) -> bitbuffer::Result<()> {
+    stream.write_int(value as u32 & ((1 << bits) - 1), bits)
+}
+
+impl SoundInfo {
+    fn read_delta(
+        stream: &mut Stream,
+        delta: &SoundInfo,
+        format: &SoundFormat,
+    ) -> bitbuffer::Result<Self> {
+        let entity = if stream.read_bool()? {
+            if stream.read_bool()? {
+                stream.read_int(5)?
+            } else {
+                stream.read_int(ENTITY_BITS)?
+            }
+        } else {
+            delta.entity
+        };
+        let sound = read_delta_uint(stream, format.sound_bits, delta.sound)?;
+        let flags = read_delta_uint(stream, format.flag_bits, delta.flags)?;
+        let channel = read_delta_uint(stream, 3, delta.channel)?;
+        let ambient = stream.read_bool()?;
+        let sentence = stream.read_bool()?;
+
+        let mut sound = SoundInfo {
+            entity,
+            sound,
+            flags,
+            channel,
+            ambient,
+            sentence,
+            ..SoundInfo::stopped()
+        };
+
+        if flags != SND_STOP {
+            sound.sequence = if stream.read_bool()? {
+                delta.sequence
+            } else if stream.read_bool()? {
+                delta.sequence + 1
+            } else {
+                stream.read_int(SEQUENCE_BITS)?
+            };
+            sound.volume = read_delta_uint(stream, 7, delta.volume)?;
+            sound.level = read_delta_uint(stream, LEVEL_BITS, delta.level)?;
+            sound.pitch = read_delta_uint(stream, 8, delta.pitch)?;
+            sound.dsp = if format.has_dsp {
+                read_delta_uint(stream, 8, delta.dsp)?
+            } else {
+                delta.dsp
+            };
+            sound.delay = if stream.read_bool()? {
+                read_signed(stream, DELAY_BITS)?
+            } else {
+                delta.delay
+            };
+            for (coord, delta) in sound.origin.iter_mut().zip(delta.origin) {
+                *coord = if stream.read_bool()? {
+                    read_signed(stream, COORD_BITS)?
+                } else {
+                    delta
+                };
+            }
+        }
+
+        Ok(sound)
+    }
+
+    fn write_delta(
+        &self,
+        stream: &mut BitWriteStream<LittleEndian>,
+        delta: &SoundInfo,
+        format: &SoundFormat,
+    ) -> bitbuffer::Result<()> {
+        stream.write_bool(self.entity != delta.entity)?;
+        if self.entity != delta.entity {
+            stream.write_bool(self.entity <= 31)?;
+            if self.entity <= 31 {
+                stream.write_int(self.entity, 5)?;
+            } else {
+                stream.write_int(self.entity, ENTITY_BITS)?;
+            }
+        }
+        write_delta_uint(stream, format.sound_bits, self.sound, delta.sound)?;
+        write_delta_uint(stream, format.flag_bits, self.flags, delta.flags)?;
+        write_delta_uint(stream, 3, self.channel, delta.channel)?;
+        stream.write_bool(self.ambient)?;
+        stream.write_bool(self.sentence)?;
+
+        if self.flags != SND_STOP {
+            if self.sequence == delta.sequence {
+                stream.write_bool(true)?;
+            } else if self.sequence == delta.sequence + 1 {
+                stream.write_bool(false)?;
+                stream.write_bool(true)?;
+            } else {
+                stream.write_bool(false)?;
+                stream.write_bool(false)?;
+                stream.write_int(self.sequence, SEQUENCE_BITS)?;
+            }
+            write_delta_uint(stream, 7, self.volume, delta.volume)?;
+            write_delta_uint(stream, LEVEL_BITS, self.level, delta.level)?;
+            write_delta_uint(stream, 8, self.pitch, delta.pitch)?;
+            if format.has_dsp {
+                write_delta_uint(stream, 8, self.dsp, delta.dsp)?;
+            }
+            stream.write_bool(self.delay != delta.delay)?;
+            if self.delay != delta.delay {
+                write_signed(stream, DELAY_BITS, self.delay)?;
+            }
+            for (coord, delta) in self.origin.iter().zip(delta.origin) {
+                stream.write_bool(*coord != delta)?;
+                if *coord != delta {
+                    write_signed(stream, COORD_BITS, *coord)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The same sound, started from the beginning instead of updating an already playing sound
+    pub(crate) fn restarted(&self) -> Self {
+        SoundInfo {
+            flags: self.flags & !(SND_CHANGE_VOL | SND_CHANGE_PITCH | SND_DELAY),
+            delay: SoundInfo::default().delay,
+            ..self.clone()
+        }
+    }
+
+    /// The values the engine resets to for stopped sounds
+    fn stopped() -> Self {
+        SoundInfo {
+            sequence: 0,
+            volume: 0,
+            level: 0,
+            pitch: 100,
+            dsp: 0,
+            delay: 100,
+            origin: [0; 3],
+            ..SoundInfo::default()
+        }
+    }
+}
+
+pub(crate) fn read_sounds(
+    message: &ParseSoundsMessage,
+    format: &SoundFormat,
+) -> Option<Vec<SoundInfo>> {
+    let mut stream = message.data.clone();
+    let mut delta = SoundInfo::default();
+    let mut sounds = Vec::with_capacity(message.num as usize);
+    for _ in 0..message.num {
+        let sound = SoundInfo::read_delta(&mut stream, &delta, format).ok()?;
+        delta = sound.clone();
+        sounds.push(sound);
+    }
+    (stream.bits_left() == 0).then_some(sounds)
+}
+
+fn write_sounds(sounds: &[SoundInfo], format: &SoundFormat) -> Option<(Vec<u8>, usize)> {
+    let mut data = Vec::new();
+    let length = {
+        let mut stream = BitWriteStream::new(&mut data, LittleEndian);
+        let mut delta = &SoundInfo::default();
+        for sound in sounds {
+            sound.write_delta(&mut stream, delta, format).ok()?;
+            delta = sound;
+        }
+        stream.bit_len()
+    };
+    Some((data, length))
+}
+
+/// Build an unreliable `ParseSounds` message containing the sounds
+pub(crate) fn sounds_message(
+    sounds: &[SoundInfo],
+    format: &SoundFormat,
+) -> Option<ParseSoundsMessage<'static>> {
+    let (data, length) = write_sounds(sounds, format)?;
+    if length > u16::MAX as usize || sounds.len() > u8::MAX as usize {
+        return None;
+    }
+    let data = BitReadStream::new(BitReadBuffer::new_owned(data, LittleEndian))
+        .read_bits(length)
+        .ok()?;
+    Some(ParseSoundsMessage {
+        reliable: false,
+        num: sounds.len() as u8,
+        length: length as u16,
+        data,
+    })
+}
+
+fn stream_bytes(stream: &Stream) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    {
+        let mut out = BitWriteStream::new(&mut data, LittleEndian);
+        stream.write(&mut out).ok()?;
+    }
+    Some(data)
+}
+
+struct SoundFilter {
+    options: SoundOptions,
+    table_names: RefCell<Vec<String>>,
+    sounds: RefCell<BTreeMap<u32, String>>,
+}
+
+impl SoundFilter {
+    fn handle_table(&self, table: &StringTable) {
+        self.table_names.borrow_mut().push(table.name.to_string());
+        if table.name == "soundprecache" {
+            self.handle_entries(table.entries.iter());
+        }
+    }
+
+    fn handle_entries<'a, 'b: 'a>(
+        &self,
+        entries: impl Iterator<Item = &'a (u16, StringTableEntry<'b>)>,
+    ) {
+        let mut sounds = self.sounds.borrow_mut();
+        for (index, entry) in entries {
+            if let Some(path) = entry.text.as_deref() {
+                sounds.insert(*index as u32, path.to_string());
+            }
+        }
+    }
+
+    fn is_sound_table(&self, table_id: u8) -> bool {
+        self.table_names
+            .borrow()
+            .get(table_id as usize)
+            .map(|name| name == "soundprecache")
+            .unwrap_or_default()
+    }
+
+    fn is_removed(&self, sound: &SoundInfo) -> bool {
+        // sentences are indexes into the sentence list, not the precache table
+        !sound.sentence
+            && self
+                .sounds
+                .borrow()
+                .get(&sound.sound)
+                .map(|path| self.options.matches(path))
+                .unwrap_or_default()
+    }
+
+    /// Remove the matching sounds from the message, returns false if no sounds are left
+    fn filter_sounds(&self, message: &mut ParseSoundsMessage, state: &ParserState) -> bool {
+        let format = SoundFormat::new(state.protocol_version);
+        let sounds = match read_sounds(message, &format) {
+            Some(sounds) => sounds,
+            None => {
+                log::warn!("failed to parse sounds, leaving them untouched");
+                return true;
+            }
+        };
+
+        // only touch the message if we can reproduce the original encoding
+        let original = stream_bytes(&message.data);
+        let encoded = write_sounds(&sounds, &format);
+        if encoded.as_ref().map(|(data, len)| (Some(data), *len))
+            != Some((original.as_ref(), message.length as usize))
+        {
+            log::warn!("failed to re-encode sounds, leaving them untouched");
+            return true;
+        }
+
+        let kept: Vec<SoundInfo> = sounds
+            .into_iter()
+            .filter(|sound| !self.is_removed(sound))
+            .collect();
+        if kept.is_empty() {
+            return false;
+        }
+        if kept.len() == message.num as usize {
+            return true;
+        }
+
+        match sounds_message(&kept, &format) {
+            Some(encoded) => *message = encoded,
+            None => log::warn!("failed to re-encode sounds, leaving them untouched"),
+        }
+        true
+    }
+
+    fn filter_message(&self, message: &mut Message, state: &ParserState) -> bool {
+        match message {
+            Message::CreateStringTable(message) => self.handle_table(&message.table),
+            Message::UpdateStringTable(message) if self.is_sound_table(message.table_id) => {
+                self.handle_entries(message.entries.iter())
+            }
+            Message::ParseSounds(message) => return self.filter_sounds(message, state),
+            _ => {}
+        }
+        true
+    }
+}
+
+impl PacketMutator for SoundFilter {
+    fn mutate_packet(&self, packet: &mut Packet, state: &ParserState) {
+        match packet {
+            Packet::StringTables(packet) => {
+                for table in packet.tables.iter() {
+                    self.handle_table(table);
+                }
+            }
+            Packet::Message(packet) | Packet::Signon(packet) => {
+                let messages = take(&mut packet.messages);
+                packet.messages = messages
+                    .into_iter()
+                    .filter_map(|mut message| {
+                        self.filter_message(&mut message, state).then_some(message)
+                    })
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Remove all sounds whose path matches one of the patterns
+pub fn remove_sounds(mutators: &mut MutatorList, options: SoundOptions) {
+    mutators.push_packet_mutator(SoundFilter {
+        options,
+        table_names: RefCell::default(),
+        sounds: RefCell::default(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_literals() {
+        assert!(glob_match("music/song.wav", "music/song.wav"));
+        assert!(!glob_match("music/song.wav", "music/song.mp3"));
+        assert!(!glob_match("music/song.wav", "music/song.wav2"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("", "a"));
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_match("music/*", "music/song.wav"));
+        assert!(glob_match("music/*", "music/"));
+        assert!(glob_match("*/taunt_*.wav", "player/taunt_laugh.wav"));
+        assert!(!glob_match("*/taunt_*.wav", "player/taunt_laugh.mp3"));
+        assert!(glob_match("*_lp.wav", "a_lp.wav_lp.wav"));
+        assert!(glob_match("so?g.wav", "song.wav"));
+        assert!(!glob_match("so?g.wav", "sog.wav"));
+        assert!(glob_match("**", ""));
+    }
+
+    #[test]
+    fn patterns_match_normalized_paths() {
+        let options = SoundOptions {
+            patterns: vec!["Music\\*".into()],
+        };
+        assert!(options.matches(")music/Song.wav"));
+        assert!(!options.matches("player/music.wav"));
+        assert!(is_looping_sound("#weapons\\Minigun_Spin.wav"));
+        assert!(!is_looping_sound("weapons/minigun_shoot.wav"));
+    }
+
+    fn round_trip(sounds: &[SoundInfo], format: &SoundFormat) {
+        let message = sounds_message(sounds, format).unwrap();
+        assert_eq!(message.num as usize, sounds.len());
+        assert_eq!(read_sounds(&message, format).unwrap(), sounds);
+
+        let (data, length) = write_sounds(sounds, format).unwrap();
+        assert_eq!(length, message.length as usize);
+        assert_eq!(stream_bytes(&message.data).unwrap(), data);
+    }
+
+    fn sounds() -> Vec<SoundInfo> {
+        let playing = SoundInfo {
+            entity: 3,
+            sound: 120,
+            flags: SND_CHANGE_VOL,
+            sequence: 5,
+            volume: 64,
+            pitch: 90,
+            delay: -20,
+            origin: [-1000, 0, 1500],
+            ..SoundInfo::default()
+        };
+        vec![
+            playing.clone(),
+            // the next sequence number and an entity that doesn't fit in 5 bits
+            SoundInfo {
+                entity: 1500,
+                sequence: 6,
+                ..playing.clone()
+            },
+            SoundInfo {
+                sequence: 300,
+                dsp: 5,
+                level: 400,
+                ..playing
+            },
+            SoundInfo {
+                entity: 3,
+                sound: 120,
+                flags: SND_STOP,
+                ..SoundInfo::stopped()
+            },
+            SoundInfo {
+                sentence: true,
+                ambient: true,
+                channel: 2,
+                ..SoundInfo::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn sounds_round_trip() {
+        round_trip(&sounds(), &SoundFormat::new(24));
+    }
+
+    #[test]
+    fn sounds_round_trip_for_old_protocols() {
+        let sounds: Vec<SoundInfo> = sounds()
+            .into_iter()
+            .map(|sound| SoundInfo {
+                // these protocols have no dsp and fewer bits for the sound index
+                dsp: 0,
+                sound: sound.sound.min(1 << 12),
+                ..sound
+            })
+            .collect();
+        round_trip(&sounds, &SoundFormat::new(18));
+    }
+
+    #[test]
+    fn truncated_sounds_are_rejected() {
+        let format = SoundFormat::new(24);
+        let mut message = sounds_message(&sounds(), &format).unwrap();
+        message.num += 1;
+        assert!(read_sounds(&message, &format).is_none());
+    }
+}
@@ -0,0 +1,80 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use tf_demo_parser::demo::data::UserInfo;
+use tf_demo_parser::demo::message::packetentities::EntityId;
+use tf_demo_parser::demo::message::Message;
+use tf_demo_parser::demo::packet::stringtable::{StringTable, StringTableEntry};
+use tf_demo_parser::demo::packet::Packet;
+
+/// Parse the player info from an entry of the `userinfo` string table, `None` for entries of other tables
+pub(crate) fn user_from_entry(
+    table: &str,
+    index: usize,
+    entry: &StringTableEntry,
+) -> Option<UserInfo> {
+    if table != "userinfo" {
+        return None;
+    }
+    UserInfo::parse_from_string_table(
+        index as u16,
+        entry.text.as_deref(),
+        entry.extra_data.as_ref().map(|data| data.data.clone()),
+    )
+    .ok()
+    .flatten()
+}
+
+/// Follow the steam ids of the players in the `userinfo` string table while the demo is being edited
+#[derive(Default)]
+pub(crate) struct UserTracker {
+    table_names: RefCell<Vec<String>>,
+    steam_ids: RefCell<BTreeMap<EntityId, String>>,
+}
+
+impl UserTracker {
+    pub fn steam_id(&self, entity: EntityId) -> Option<String> {
+        self.steam_ids.borrow().get(&entity).cloned()
+    }
+
+    pub fn handle_packet(&self, packet: &Packet) {
+        match packet {
+            Packet::StringTables(packet) => {
+                for table in &packet.tables {
+                    self.handle_table(table);
+                }
+            }
+            Packet::Message(packet) | Packet::Signon(packet) => {
+                for message in &packet.messages {
+                    match message {
+                        Message::CreateStringTable(message) => self.handle_table(&message.table),
+                        Message::UpdateStringTable(message) => {
+                            let table_names = self.table_names.borrow();
+                            if let Some(name) = table_names.get(message.table_id as usize) {
+                                for (index, entry) in &message.entries {
+                                    self.handle_entry(name, *index as usize, entry);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_table(&self, table: &StringTable) {
+        self.table_names.borrow_mut().push(table.name.to_string());
+        for (index, entry) in &table.entries {
+            self.handle_entry(&table.name, *index as usize, entry);
+        }
+    }
+
+    fn handle_entry(&self, table: &str, index: usize, entry: &StringTableEntry) {
+        if let Some(user) = user_from_entry(table, index, entry) {
+            self.steam_ids
+                .borrow_mut()
+                .insert(user.entity_id, user.player_info.steam_id);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::mutate::MutatorList;
+use bitbuffer::{BitWrite, BitWriteStream, LittleEndian};
+use serde::{Deserialize, Serialize};
+use tf_demo_parser::demo::message::packetentities::EntityId;
+use tf_demo_parser::demo::message::voice::VoiceDataMessage;
+use tf_demo_parser::demo::message::Message;
+
+/// Remove voice data, either for all players or only the listed player entities
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct VoiceOptions {
+    #[serde(default)]
+    pub players: Vec<EntityId>,
+}
+
+pub fn remove_voice(mutators: &mut MutatorList, options: VoiceOptions) {
+    mutators.push_message_filter(move |message: &Message| match message {
+        Message::VoiceInit(_) => !options.players.is_empty(),
+        Message::VoiceData(voice) if !options.players.is_empty() => voice_entity(voice)
+            .map(|entity| !options.players.contains(&entity))
+            .unwrap_or(true),
+        Message::VoiceData(_) => false,
+        _ => true,
+    });
+}
+
+/// Get the entity of the speaker
+fn voice_entity(message: &VoiceDataMessage) -> Option<EntityId> {
+    // the client index isn't exposed, but it's the first byte of the encoded message
+    let mut data = Vec::new();
+    {
+        let mut stream = BitWriteStream::new(&mut data, LittleEndian);
+        message.write(&mut stream).ok()?;
+    }
+    let client = *data.first()?;
+    Some(EntityId::from(client as u32 + 1))
+}
@@ -0,0 +1,671 @@
+use bitbuffer::BitRead;
+use edit::{
+    anonymize_mapping, compare_state, edit, edit_with_report, find_highlights, AnonymizeOptions,
+    DemoBuilder, Divergence, EditOptions, HighlightOptions, SeekIndex, TickNumbering, TickRange,
+};
+use tf_demo_parser::demo::data::userinfo::PlayerInfo;
+use tf_demo_parser::demo::data::{DemoTick, UserInfo};
+use tf_demo_parser::demo::gameevent_gen::PlayerDeathEvent;
+use tf_demo_parser::demo::gamevent::GameEvent;
+use tf_demo_parser::demo::header::Header;
+use tf_demo_parser::demo::message::packetentities::{EntityId, UpdateType};
+use tf_demo_parser::demo::message::usermessage::UserMessage;
+use tf_demo_parser::demo::message::{Message, SetPauseMessage};
+use tf_demo_parser::demo::packet::Packet;
+use tf_demo_parser::demo::parser::{DemoHandler, RawPacketStream};
+use tf_demo_parser::demo::sendprop::{SendPropIdentifier, SendPropValue};
+use tf_demo_parser::{Demo, DemoParser};
+
+/// Generated by `cargo run --example make_template`
+const TEMPLATE: &[u8] = include_bytes!("data/template.dem");
+
+const TEAM: SendPropIdentifier = SendPropIdentifier::new("DT_BaseEntity", "m_iTeamNum");
+const FLAGS: SendPropIdentifier = SendPropIdentifier::new("DT_BasePlayer", "m_fFlags");
+const COND: SendPropIdentifier = SendPropIdentifier::new("DT_TFPlayerShared", "m_nPlayerCond");
+
+fn entity(id: u32) -> EntityId {
+    EntityId::from(id)
+}
+
+fn tick(tick: u32) -> DemoTick {
+    DemoTick::from(tick)
+}
+
+fn death(attacker: u16, victim: u16, weapon: &str) -> GameEvent {
+    GameEvent::PlayerDeath(Box::new(PlayerDeathEvent {
+        user_id: victim,
+        victim_ent_index: 0,
+        inflictor_ent_index: 0,
+        attacker,
+        weapon: weapon.into(),
+        weapon_id: 0,
+        damage_bits: 0,
+        custom_kill: 0,
+        assister: u16::MAX,
+        weapon_log_class_name: weapon.into(),
+        stun_flags: 0,
+        death_flags: 0,
+        silent_kill: false,
+        player_penetrate_count: 0,
+        assister_fallback: "".into(),
+        kill_streak_total: 0,
+        kill_streak_wep: 0,
+        kill_streak_assist: 0,
+        kill_streak_victim: 0,
+        ducks_streaked: 0,
+        duck_streak_total: 0,
+        duck_streak_assist: 0,
+        duck_streak_victim: 0,
+        rocket_jump: false,
+        weapon_def_index: 0,
+        crit_type: 0,
+    }))
+}
+
+/// Two players moving around for 100 ticks, one leaves the PVS and comes back, the other is deleted
+fn match_demo() -> Vec<u8> {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))])
+        .enter(entity(3), "CTFPlayer", [(TEAM, SendPropValue::Integer(3))]);
+    for i in 2..=100 {
+        builder.tick(tick(i));
+        if i % 3 == 0 && !(30..=50).contains(&i) {
+            builder.update(entity(2), [(FLAGS, SendPropValue::Integer(i as i64 % 2))]);
+        }
+        if i % 5 == 0 && i < 60 {
+            builder.update(entity(3), [(COND, SendPropValue::Integer(i as i64))]);
+        }
+    }
+    builder.tick(tick(30)).leave(entity(2));
+    builder
+        .tick(tick(50))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))]);
+    builder.tick(tick(60)).leave(entity(3));
+    builder.tick(tick(70)).remove(entity(3));
+    builder.build()
+}
+
+#[test]
+fn cut_matches_original_state() {
+    let input = match_demo();
+    let options = EditOptions {
+        cut: Some(TickRange {
+            from: tick(40),
+            to: tick(80),
+        }),
+        ..EditOptions::default()
+    };
+    let (output, report) = edit_with_report(&input, options, None).unwrap();
+    assert_eq!(report.cut.map(|cut| cut.from), Some(tick(40)));
+
+    let comparison = compare_state(&input, &output, tick(40));
+    assert!(comparison.ticks > 0);
+    assert!(comparison.matches(), "{:?}", comparison.divergences);
+}
+
+#[test]
+fn entity_fixer_drops_unknown_removals() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))])
+        .tick(tick(2))
+        .remove(entity(5))
+        .tick(tick(3))
+        .update(entity(2), [(TEAM, SendPropValue::Integer(3))]);
+    let input = builder.build();
+
+    let output = edit(&input, EditOptions::default()).unwrap();
+    let comparison = compare_state(&input, &output, tick(0));
+    assert_eq!(comparison.ticks, 3);
+    assert!(comparison.matches(), "{:?}", comparison.divergences);
+}
+
+#[test]
+fn entity_fixer_follows_synced_entities() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    for i in 1..=40 {
+        builder.tick(tick(i));
+    }
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))])
+        .tick(tick(20))
+        .leave(entity(2))
+        .tick(tick(30))
+        .remove(entity(2));
+    let input = builder.build();
+
+    // the entity only exists in the synced state at the start of the cut
+    let options = EditOptions {
+        cut: Some(TickRange {
+            from: tick(10),
+            to: tick(40),
+        }),
+        ..EditOptions::default()
+    };
+    let output = edit(&input, options).unwrap();
+    let comparison = compare_state(&input, &output, tick(10));
+    assert!(comparison.matches(), "{:?}", comparison.divergences);
+}
+
+#[test]
+fn entity_fixer_removes_entities_in_pvs_after_they_leave() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))])
+        .tick(tick(2))
+        .remove(entity(2))
+        .tick(tick(3));
+    let input = builder.build();
+
+    let output = edit(&input, EditOptions::default()).unwrap();
+    assert!(compare_state(&input, &output, tick(0)).matches());
+
+    let demo = Demo::new(&output);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).unwrap();
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+    let mut packets = RawPacketStream::new(stream);
+    let mut leaves = Vec::new();
+    let mut removals = Vec::new();
+    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+        if let Packet::Message(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::PacketEntities(message) = message {
+                    leaves.extend(
+                        message
+                            .entities
+                            .iter()
+                            .filter(|entity| entity.update_type == UpdateType::Leave)
+                            .map(|entity| (message_packet.tick, entity.entity_index)),
+                    );
+                    removals.extend(
+                        message
+                            .removed_entities
+                            .iter()
+                            .map(|id| (message_packet.tick, *id)),
+                    );
+                }
+            }
+        }
+        handler.handle_packet(packet).unwrap();
+    }
+    assert_eq!(leaves, vec![(tick(2), entity(2))]);
+    assert_eq!(removals, vec![(tick(3), entity(2))]);
+}
+
+#[test]
+fn unlock_pov_adds_stv_entity() {
+    let input = match_demo();
+    let options = EditOptions {
+        unlock_pov: true,
+        ..EditOptions::default()
+    };
+    let output = edit(&input, options).unwrap();
+
+    let comparison = compare_state(&input, &output, tick(0));
+    assert!(!comparison.matches());
+    for divergence in comparison.divergences {
+        assert_eq!(
+            divergence.divergences,
+            vec![Divergence::ExtraEntity {
+                entity: entity(1),
+                class: "CTFPlayer".into(),
+            }]
+        );
+    }
+}
+
+#[test]
+fn cond_mask_strips_conditions() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(
+            entity(2),
+            "CTFPlayer",
+            [(COND, SendPropValue::Integer(0b11))],
+        )
+        .enter(
+            entity(3),
+            "CTFPlayer",
+            [(COND, SendPropValue::Integer(0b11))],
+        )
+        .tick(tick(2))
+        .update(entity(2), [(COND, SendPropValue::Integer(0b111))]);
+    let input = builder.build();
+
+    let options: EditOptions = serde_json::from_str(
+        r#"{"unlock_pov": false, "remove_conditions": [{"entity": 2, "mask": 1}]}"#,
+    )
+    .unwrap();
+    let output = edit(&input, options).unwrap();
+
+    let comparison = compare_state(&input, &output, tick(0));
+    let divergences: Vec<_> = comparison
+        .divergences
+        .iter()
+        .map(|divergence| (divergence.tick, divergence.divergences.clone()))
+        .collect();
+    let cond = |original: &str| Divergence::Prop {
+        entity: entity(2),
+        prop: COND.to_string(),
+        original: Some(original.into()),
+        edited: Some("1".into()),
+    };
+    assert_eq!(
+        divergences,
+        vec![(tick(1), vec![cond("3")]), (tick(2), vec![cond("7")])]
+    );
+}
+
+#[test]
+#[should_panic(expected = "entities can't be removed on the first tick")]
+fn remove_on_first_tick_panics() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder.tick(tick(1)).remove(entity(2));
+    builder.build();
+}
+
+#[test]
+fn new_entities_get_new_serials() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [])
+        .enter(entity(3), "CTFPlayer", [])
+        .tick(tick(2))
+        .delete(entity(2))
+        .leave(entity(3))
+        .tick(tick(3))
+        .enter(entity(2), "CTFPlayer", [])
+        .enter(entity(3), "CTFPlayer", []);
+    let input = builder.build();
+
+    let demo = Demo::new(&input);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).unwrap();
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+    let mut packets = RawPacketStream::new(stream);
+    let mut serials = Vec::new();
+    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+        if let Packet::Message(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::PacketEntities(message) = message {
+                    serials.extend(
+                        message
+                            .entities
+                            .iter()
+                            .filter(|entity| entity.update_type == UpdateType::Enter)
+                            .map(|entity| entity.serial_number),
+                    );
+                }
+            }
+        }
+        handler.handle_packet(packet).unwrap();
+    }
+    assert_eq!(serials, vec![1, 2, 3, 2]);
+}
+
+#[test]
+fn builder_writes_players_and_events() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))])
+        .enter(entity(3), "CTFPlayer", [(TEAM, SendPropValue::Integer(3))])
+        .player(entity(2), 10, "attacker", "[U:1:10]")
+        .player(entity(3), 11, "victim", "[U:1:11]")
+        .tick(tick(5))
+        .event(death(10, 11, "scattergun"));
+    let input = builder.build();
+
+    let (_, state) = DemoParser::new(Demo::new(&input).get_stream())
+        .parse()
+        .unwrap();
+    let users: Vec<_> = state
+        .users
+        .values()
+        .map(|user| (user.name.as_str(), user.steam_id.as_str(), user.entity_id))
+        .collect();
+    assert_eq!(
+        users,
+        vec![
+            ("attacker", "[U:1:10]", entity(2)),
+            ("victim", "[U:1:11]", entity(3))
+        ]
+    );
+    assert_eq!(state.deaths.len(), 1);
+    assert_eq!(state.deaths[0].killer, 10u16);
+    assert_eq!(state.deaths[0].victim, 11u16);
+    assert_eq!(state.deaths[0].weapon, "scattergun");
+    assert_eq!(state.deaths[0].tick, tick(5));
+}
+
+/// The players in the `userinfo` string table updates of the demo
+fn user_infos(input: &[u8]) -> Vec<PlayerInfo> {
+    let demo = Demo::new(input);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).unwrap();
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+    let mut packets = RawPacketStream::new(stream);
+    let mut users = Vec::new();
+    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+        if let Packet::Message(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::UpdateStringTable(message) = message {
+                    for (index, entry) in &message.entries {
+                        let user = UserInfo::parse_from_string_table(
+                            *index,
+                            entry.text.as_deref(),
+                            entry.extra_data.as_ref().map(|data| data.data.clone()),
+                        );
+                        users.extend(user.unwrap().map(|user| user.player_info));
+                    }
+                }
+            }
+        }
+        handler.handle_packet(packet).unwrap();
+    }
+    users
+}
+
+#[test]
+fn anonymize_rewrites_user_info() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [])
+        .player(entity(2), 10, "player", "[U:1:1234]")
+        .tick(tick(2));
+    let input = builder.build();
+    assert_eq!(user_infos(&input)[0].friends_id, 1234);
+
+    let anonymize = AnonymizeOptions {
+        seed: "seed".into(),
+    };
+    let mapping = anonymize_mapping(&input, &anonymize).unwrap();
+    let options = EditOptions {
+        anonymize: Some(anonymize),
+        ..EditOptions::default()
+    };
+    let output = edit(&input, options).unwrap();
+
+    let users = user_infos(&output);
+    assert_eq!(users.len(), 1);
+    let user = &users[0];
+    assert_eq!(user.name.as_ref(), mapping.names["player"]);
+    assert_eq!(user.steam_id.as_ref(), mapping.steam_ids["[U:1:1234]"]);
+    assert_eq!(user.user_id, 10u16);
+    assert_eq!(user.friends_id, 0);
+    assert_eq!(user.friends_name_bytes, [0; 32]);
+    assert_eq!(user.custom_file, [0; 4]);
+}
+
+#[test]
+fn analysis_of_broken_demo_fails() {
+    let mut input = match_demo();
+    // the type of the first packet after the header
+    input[1072] = 0xff;
+    let options = HighlightOptions::new("[U:1:10]".into());
+    assert!(find_highlights(&input, &options).is_err());
+    assert!(SeekIndex::new(&input[..500]).is_err());
+}
+
+#[test]
+fn dropped_message_packets_are_integrity_errors() {
+    let mut input = match_demo();
+    let header = Header::read(&mut Demo::new(&input).get_stream()).unwrap();
+    // the first message of the first packet after the signon: type, tick, meta and length come before it
+    let first_message = 1072 + header.signon as usize + 1 + 4 + 84 + 4;
+    input[first_message] = 0xff;
+
+    assert!(edit(&input, EditOptions::default()).is_err());
+
+    let options = EditOptions {
+        lenient: true,
+        ..EditOptions::default()
+    };
+    let (_, report) = edit_with_report(&input, options, None).unwrap();
+    // the updates after the dropped packet can't be decoded either, since the entities entered in it
+    assert!(!report.skipped.is_empty());
+    assert!(report.skipped.iter().all(|skipped| !skipped.copied));
+    assert_eq!(report.integrity_errors.len(), report.skipped.len());
+}
+
+#[test]
+fn remove_ranges_without_cut() {
+    let input = match_demo();
+    let options: EditOptions =
+        serde_json::from_str(r#"{"unlock_pov": false, "remove_ranges": [{"from": 20, "to": 29}]}"#)
+            .unwrap();
+    let (output, report) = edit_with_report(&input, options, None).unwrap();
+    assert_eq!(
+        report.removed,
+        vec![TickRange {
+            from: tick(20),
+            to: tick(29)
+        }]
+    );
+    assert_eq!(report.ticks, 90);
+
+    // the sync at tick 0 already has the entities from the first packet at tick 1,
+    // from there on up to the removed range the state is unchanged
+    let comparison = compare_state(&input, &output, tick(0));
+    let diverged: Vec<_> = comparison
+        .divergences
+        .iter()
+        .filter(|divergence| (1..20).contains(&u32::from(divergence.tick)))
+        .collect();
+    assert!(diverged.is_empty(), "{:?}", diverged);
+    DemoParser::new(Demo::new(&output).get_stream())
+        .parse()
+        .unwrap();
+}
+
+#[test]
+fn remove_idle_without_cut() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))]);
+    for i in 2..=200 {
+        builder
+            .tick(tick(i))
+            .update(entity(2), [(FLAGS, SendPropValue::Integer(i as i64 % 2))]);
+    }
+    builder
+        .tick(tick(50))
+        .message(Message::SetPause(SetPauseMessage { pause: true }))
+        .tick(tick(100))
+        .message(Message::SetPause(SetPauseMessage { pause: false }));
+    let input = builder.build();
+
+    let options: EditOptions =
+        serde_json::from_str(r#"{"unlock_pov": false, "remove_idle": {"min_length": 0.1}}"#)
+            .unwrap();
+    let (output, report) = edit_with_report(&input, options, None).unwrap();
+    assert_eq!(
+        report.removed,
+        vec![TickRange {
+            from: tick(50),
+            to: tick(99)
+        }]
+    );
+    assert_eq!(report.ticks, 150);
+    DemoParser::new(Demo::new(&output).get_stream())
+        .parse()
+        .unwrap();
+}
+
+#[test]
+fn invalid_remove_ranges_are_rejected() {
+    let input = match_demo();
+    for ranges in [
+        r#"[{"from": 100, "to": 50}]"#,
+        r#"[{"from": 10, "to": 50}, {"from": 40, "to": 60}]"#,
+        r#"[{"from": 40, "to": 60}, {"from": 10, "to": 40}]"#,
+    ] {
+        let options: EditOptions = serde_json::from_str(&format!(
+            r#"{{"unlock_pov": false, "remove_ranges": {}}}"#,
+            ranges
+        ))
+        .unwrap();
+        assert!(edit(&input, options).is_err(), "{}", ranges);
+    }
+}
+
+#[test]
+fn cut_fills_only_referenced_deltas() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [(TEAM, SendPropValue::Integer(2))]);
+    for i in (2..=10).chain(40..=60) {
+        builder.tick(tick(i));
+        builder.update(entity(2), [(FLAGS, SendPropValue::Integer(i as i64 % 2))]);
+    }
+    builder
+        .tick(tick(42))
+        .enter(entity(3), "CTFPlayer", [(TEAM, SendPropValue::Integer(3))]);
+    let input = builder.build();
+    let options = EditOptions {
+        cut: Some(TickRange {
+            from: tick(40),
+            to: tick(60),
+        }),
+        ..EditOptions::default()
+    };
+    let output = edit(&input, options).unwrap();
+
+    let demo = Demo::new(&output);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).unwrap();
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+    let mut packets = RawPacketStream::new(stream);
+    let mut empty_updates = 0;
+    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+        if let Packet::Message(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::PacketEntities(message) = message {
+                    if message_packet.tick == tick(0)
+                        && message.entities.is_empty()
+                        && message.removed_entities.is_empty()
+                        && message.delta.is_some()
+                    {
+                        empty_updates += 1;
+                    }
+                }
+            }
+        }
+        handler.handle_packet(packet).unwrap();
+    }
+    // the update with the removed entities and a single filler tick instead of one for each of the 30 skipped ticks
+    assert_eq!(empty_updates, 2);
+}
+
+#[test]
+fn chat_filter_matches_players_by_steam_id() {
+    let mut builder = DemoBuilder::from_template(TEMPLATE).unwrap();
+    builder
+        .tick(tick(1))
+        .enter(entity(2), "CTFPlayer", [])
+        .enter(entity(3), "CTFPlayer", [])
+        .player(entity(2), 10, "first", "[U:1:10]")
+        .player(entity(3), 11, "second", "[U:1:11]")
+        .tick(tick(2))
+        .chat(entity(2), "first", "from the first player")
+        // the second player took the name of the first
+        .chat(entity(3), "first", "from the second player")
+        .tick(tick(3));
+    let input = builder.build();
+
+    let options: EditOptions = serde_json::from_value(serde_json::json!({
+        "unlock_pov": false,
+        "filter_chat": {"players": ["[U:1:10]"]},
+    }))
+    .unwrap();
+    let output = edit(&input, options).unwrap();
+
+    let demo = Demo::new(&output);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).unwrap();
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+    let mut packets = RawPacketStream::new(stream);
+    let mut chat = Vec::new();
+    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+        if let Packet::Message(message_packet) = &packet {
+            for message in &message_packet.messages {
+                if let Message::UserMessage(UserMessage::SayText2(message)) = message {
+                    chat.push(message.text.to_string());
+                }
+            }
+        }
+        handler.handle_packet(packet).unwrap();
+    }
+    assert_eq!(chat, vec!["from the second player"]);
+}
+
+/// The first and last tick of the message packets in the demo
+fn message_tick_range(input: &[u8]) -> (DemoTick, DemoTick) {
+    let demo = Demo::new(input);
+    let mut stream = demo.get_stream();
+    let header = Header::read(&mut stream).unwrap();
+    let mut handler = DemoHandler::default();
+    handler.handle_header(&header);
+    let mut packets = RawPacketStream::new(stream);
+    let mut ticks = Vec::new();
+    while let Some(packet) = packets.next(&handler.state_handler).unwrap() {
+        if let Packet::Message(message_packet) = &packet {
+            ticks.push(message_packet.tick);
+        }
+        handler.handle_packet(packet).unwrap();
+    }
+    (ticks[0], ticks[ticks.len() - 1])
+}
+
+#[test]
+fn tick_numbering_of_cut() {
+    let input = match_demo();
+    let cut = |tick_numbering: TickNumbering, remove_ranges: Vec<TickRange>| {
+        let options = EditOptions {
+            cut: Some(TickRange {
+                from: tick(40),
+                to: tick(80),
+            }),
+            tick_numbering,
+            remove_ranges,
+            ..EditOptions::default()
+        };
+        let (output, report) = edit_with_report(&input, options, None).unwrap();
+        (message_tick_range(&output), report.ticks)
+    };
+
+    assert_eq!(cut(TickNumbering::Zero, vec![]), ((tick(0), tick(40)), 40));
+    assert_eq!(
+        cut(TickNumbering::Original, vec![]),
+        ((tick(40), tick(80)), 40)
+    );
+    assert_eq!(
+        cut(TickNumbering::Base(tick(1000)), vec![]),
+        ((tick(1000), tick(1040)), 40)
+    );
+    // removed ticks shift the ticks after them down
+    let removed = vec![TickRange {
+        from: tick(50),
+        to: tick(59),
+    }];
+    assert_eq!(
+        cut(TickNumbering::Original, removed),
+        ((tick(40), tick(70)), 30)
+    );
+}